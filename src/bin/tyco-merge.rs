@@ -0,0 +1,53 @@
+//! Git merge driver for `.tyco` files. Configure it with
+//!
+//! ```text
+//! git config merge.tyco.driver "tyco-merge %O %A %B"
+//! echo "*.tyco merge=tyco" >> .gitattributes
+//! ```
+//!
+//! The merged document is written to `%A`: our file as it was, with only
+//! the declarations the merge changes rewritten. Conflicts are printed to
+//! stderr and make the driver exit with status 1, leaving our side of each
+//! conflicting change in the file. Errors exit with status 2 without
+//! touching `%A`, and so do files using `#include` or `#import`, which git
+//! hands over without the files they name. Instances and globals the
+//! merged document no longer uses are reported as warnings on stderr; they
+//! do not change the exit status.
+
+use std::{env, fs, process::ExitCode};
+
+use tyco_rust::{merge_sources, TycoError};
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let [base, ours, theirs] = args.as_slice() else {
+        eprintln!("usage: tyco-merge BASE OURS THEIRS");
+        return ExitCode::from(2);
+    };
+    match run(base, ours, theirs) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(err) => {
+            eprintln!("tyco-merge: {err}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Merges the three files into `ours`, returning whether the merge was clean.
+fn run(base: &str, ours: &str, theirs: &str) -> Result<bool, TycoError> {
+    let read = |path: &str| fs::read_to_string(path).map_err(TycoError::Io);
+    let merged = merge_sources(&read(base)?, &read(ours)?, &read(theirs)?)?;
+    for conflict in &merged.outcome.conflicts {
+        eprintln!("{conflict}");
+    }
+    let context = &merged.outcome.context;
+    for unused in context.unused_instances() {
+        eprintln!("warning: {} is not referenced", unused.path);
+    }
+    for name in context.unused_globals() {
+        eprintln!("warning: global '{name}' is not used by any template");
+    }
+    fs::write(ours, merged.text).map_err(TycoError::Io)?;
+    Ok(merged.outcome.is_clean())
+}
@@ -0,0 +1,467 @@
+//! Fluent construction of contexts in code. Every global, struct and instance
+//! is checked against the schema when it is added; `build` reports the first
+//! problem found and renders the result.
+
+use crate::{
+    context::{FieldSchema, TycoContext, TycoStruct},
+    error::TycoError,
+    parser::{is_scalar_type, parse_scalar, TycoParser},
+    value::{TycoInstance, TycoValue},
+};
+
+/// Builds a `TycoContext`; see `TycoContext::builder`.
+///
+/// ```
+/// use tyco_rust::TycoContext;
+///
+/// let context = TycoContext::builder()
+///     .global("timezone", "UTC")
+///     .struct_def("Host", |s| s.pk_str("hostname").int("cores").default("cores", 4))
+///     .instance("Host", |i| i.set("hostname", "prod-01").set("cores", 16))
+///     .instance("Host", |i| i.set("hostname", "prod-02"))
+///     .build()
+///     .unwrap();
+/// assert_eq!(context.to_json()["Host"][1]["cores"], 4);
+/// ```
+#[derive(Debug, Default)]
+pub struct ContextBuilder {
+    context: TycoContext,
+    error: Option<TycoError>,
+}
+
+/// Declares the fields of one struct; see `ContextBuilder::struct_def`.
+///
+/// Field types use the declaration syntax of `.tyco` files: `str`, `?int`
+/// (nullable), `str[]` (array), `*str` (primary key) or a struct name for
+/// references.
+#[derive(Debug)]
+pub struct StructBuilder {
+    tyco_struct: TycoStruct,
+    error: Option<TycoError>,
+}
+
+/// Sets the fields of one instance; see `ContextBuilder::instance`.
+#[derive(Debug)]
+pub struct InstanceBuilder {
+    instance: TycoInstance,
+}
+
+impl ContextBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a global whose type is taken from the value: `str`, `int`,
+    /// `Host` for a reference, `int[]` for an array of ints. Use
+    /// `typed_global` for nullable globals and empty arrays.
+    pub fn global(self, name: &str, value: impl Into<TycoValue>) -> Self {
+        let value = value.into();
+        match infer_type(&value) {
+            Some(type_name) => self.typed_global(name, &type_name, value),
+            None => self.fail(TycoError::Schema(format!(
+                "Cannot infer the type of global '{name}'; use typed_global"
+            ))),
+        }
+    }
+
+    /// Adds a global with a declared type such as `?str` or `int[]`.
+    pub fn typed_global(
+        mut self,
+        name: &str,
+        type_name: &str,
+        value: impl Into<TycoValue>,
+    ) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        if !TycoParser::is_valid_field_name(name) {
+            return self.fail(TycoError::Schema(format!("Invalid global name '{name}'")));
+        }
+        if self.context.globals().contains_key(name) {
+            return self.fail(TycoError::Schema(format!(
+                "Global '{name}' is already defined"
+            )));
+        }
+        let field = match parse_declaration(name, type_name) {
+            Ok(field) if !field.is_primary_key => field,
+            Ok(_) => {
+                return self.fail(TycoError::Schema(format!(
+                    "Global '{name}' cannot be a primary key"
+                )))
+            }
+            Err(err) => return self.fail(err),
+        };
+        match coerce(value.into(), &field, name) {
+            Ok(value) => {
+                self.context.set_global_type(name.to_string(), type_name.trim().to_string());
+                self.context.set_global(name, value);
+                self
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Declares a struct. Field types naming other structs are checked by
+    /// `build`, so structs may reference structs declared after them.
+    pub fn struct_def(
+        mut self,
+        name: &str,
+        fields: impl FnOnce(StructBuilder) -> StructBuilder,
+    ) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let valid_name = name.starts_with(|c: char| c.is_ascii_uppercase())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return self.fail(TycoError::Schema(format!("Invalid struct name '{name}'")));
+        }
+        if self.context.get_struct(name).is_some() {
+            return self.fail(TycoError::Schema(format!(
+                "Struct '{name}' is already defined"
+            )));
+        }
+        let builder = fields(StructBuilder {
+            tyco_struct: TycoStruct::new(name),
+            error: None,
+        });
+        match builder.error {
+            Some(err) => self.fail(err),
+            None => {
+                self.context.add_struct(builder.tyco_struct);
+                self
+            }
+        }
+    }
+
+    /// Adds an instance of a declared struct. Fields must exist in the
+    /// schema and hold values of their declared type; fields with a default
+    /// may be left out, nullable fields without one default to null.
+    pub fn instance(
+        mut self,
+        struct_name: &str,
+        fields: impl FnOnce(InstanceBuilder) -> InstanceBuilder,
+    ) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let Some(struct_def) = self.context.get_struct(struct_name) else {
+            return self.fail(TycoError::UnknownStruct(struct_name.to_string()));
+        };
+        let written = fields(InstanceBuilder {
+            instance: TycoInstance::new(struct_name),
+        })
+        .instance;
+        match checked_instance(struct_def, written) {
+            Ok(instance) => {
+                if let Some(struct_def) = self.context.get_struct_mut(struct_name) {
+                    struct_def.add_instance(instance);
+                }
+                self
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Checks the references between structs and renders the context.
+    pub fn build(self) -> Result<TycoContext, TycoError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let mut context = self.context;
+        check_field_types(&context)?;
+        context.render()?;
+        Ok(context)
+    }
+
+    fn fail(mut self, err: TycoError) -> Self {
+        self.error.get_or_insert(err);
+        self
+    }
+}
+
+impl StructBuilder {
+    /// Declares a field with a type written as in a `.tyco` file, e.g.
+    /// `*str`, `?int`, `str[]` or `Host`.
+    pub fn field(mut self, name: &str, type_name: &str) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let qualified = format!("{}.{name}", self.tyco_struct.name());
+        let error = if !TycoParser::is_valid_field_name(name) {
+            TycoError::Schema(format!("Invalid field name '{qualified}'"))
+        } else if self.tyco_struct.fields().iter().any(|f| f.name == name) {
+            TycoError::Schema(format!("Field '{qualified}' is already defined"))
+        } else {
+            match parse_declaration(name, type_name) {
+                Ok(field) => {
+                    self.tyco_struct.add_field(field);
+                    return self;
+                }
+                Err(err) => err,
+            }
+        };
+        self.error = Some(error);
+        self
+    }
+
+    /// Sets the default of a declared field.
+    pub fn default(mut self, name: &str, value: impl Into<TycoValue>) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let qualified = format!("{}.{name}", self.tyco_struct.name());
+        let result = match self.tyco_struct.fields().iter().find(|f| f.name == name) {
+            Some(field) => coerce(value.into(), field, &qualified),
+            None => Err(TycoError::Schema(format!(
+                "Field '{qualified}' is not declared"
+            ))),
+        };
+        match result.and_then(|value| self.tyco_struct.set_default(name, Some(value))) {
+            Ok(()) => {}
+            Err(err) => self.error = Some(err),
+        }
+        self
+    }
+
+    pub fn pk_str(self, name: &str) -> Self {
+        self.field(name, "*str")
+    }
+
+    pub fn pk_int(self, name: &str) -> Self {
+        self.field(name, "*int")
+    }
+
+    pub fn str(self, name: &str) -> Self {
+        self.field(name, "str")
+    }
+
+    pub fn int(self, name: &str) -> Self {
+        self.field(name, "int")
+    }
+
+    pub fn uint(self, name: &str) -> Self {
+        self.field(name, "uint")
+    }
+
+    pub fn float(self, name: &str) -> Self {
+        self.field(name, "float")
+    }
+
+    pub fn decimal(self, name: &str) -> Self {
+        self.field(name, "decimal")
+    }
+
+    pub fn bool(self, name: &str) -> Self {
+        self.field(name, "bool")
+    }
+
+    pub fn date(self, name: &str) -> Self {
+        self.field(name, "date")
+    }
+
+    pub fn time(self, name: &str) -> Self {
+        self.field(name, "time")
+    }
+
+    pub fn datetime(self, name: &str) -> Self {
+        self.field(name, "datetime")
+    }
+
+    pub fn bytes(self, name: &str) -> Self {
+        self.field(name, "bytes")
+    }
+
+    /// A reference to an instance of `struct_name`.
+    pub fn reference(self, name: &str, struct_name: &str) -> Self {
+        self.field(name, struct_name)
+    }
+}
+
+impl InstanceBuilder {
+    pub fn set(mut self, name: &str, value: impl Into<TycoValue>) -> Self {
+        self.instance.set_attribute(name, value.into());
+        self
+    }
+}
+
+/// Validates the fields written on an instance against its struct.
+pub(crate) fn checked_instance(
+    struct_def: &TycoStruct,
+    written: TycoInstance,
+) -> Result<TycoInstance, TycoError> {
+    let mut instance = TycoInstance::new(struct_def.name());
+    for (name, value) in written.attributes() {
+        // Defaults filled in by rendering are filled in again by the next one.
+        if written.is_default(name) {
+            continue;
+        }
+        let qualified = format!("{}.{name}", struct_def.name());
+        let Some(field) = struct_def.fields().iter().find(|f| &f.name == name) else {
+            return Err(TycoError::Schema(format!(
+                "Field '{qualified}' is not declared"
+            )));
+        };
+        instance.set_attribute(name.clone(), coerce(value.clone(), field, &qualified)?);
+    }
+    for field in struct_def.fields() {
+        if instance.has_attribute(&field.name) || field.default_value.is_some() {
+            continue;
+        }
+        if !field.is_nullable {
+            return Err(TycoError::Schema(format!(
+                "{} instance is missing required field '{}'",
+                struct_def.name(),
+                field.name
+            )));
+        }
+        instance.set_attribute(field.name.clone(), TycoValue::Null);
+    }
+    if let Some(key) = struct_def.primary_key_of(&instance) {
+        let duplicate = struct_def
+            .instances()
+            .iter()
+            .any(|existing| struct_def.primary_key_of(existing).as_ref() == Some(&key));
+        if duplicate {
+            return Err(TycoError::Schema(format!(
+                "Duplicate primary key {}({key})",
+                struct_def.name()
+            )));
+        }
+    }
+    Ok(instance)
+}
+
+/// Checks that every field type is a scalar type or a declared struct.
+pub(crate) fn check_field_types(context: &TycoContext) -> Result<(), TycoError> {
+    for struct_def in context.structs().values() {
+        for field in struct_def.fields() {
+            if !is_scalar_type(&field.type_name) && context.get_struct(&field.type_name).is_none() {
+                return Err(TycoError::Schema(format!(
+                    "Field '{}.{}' has unknown type '{}'",
+                    struct_def.name(),
+                    field.name,
+                    field.type_name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every instance has a value for each required field.
+pub(crate) fn check_required_fields(context: &TycoContext) -> Result<(), TycoError> {
+    for struct_def in context.structs().values() {
+        for (index, instance) in struct_def.instances().iter().enumerate() {
+            let missing = struct_def.fields().iter().find(|field| {
+                !field.is_nullable
+                    && field.default_value.is_none()
+                    && !instance.has_attribute(&field.name)
+            });
+            if let Some(field) = missing {
+                return Err(TycoError::Schema(format!(
+                    "{} is missing required field '{}'",
+                    struct_def.instance_path(index, instance),
+                    field.name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses `*str`, `?int`, `str[]` or `Host` into a field schema.
+pub(crate) fn parse_declaration(name: &str, declaration: &str) -> Result<FieldSchema, TycoError> {
+    let mut type_name = declaration.trim();
+    let is_primary_key = type_name.starts_with('*');
+    let is_nullable = type_name.starts_with('?');
+    if is_primary_key || is_nullable {
+        type_name = &type_name[1..];
+    }
+    let is_array = type_name.ends_with("[]");
+    type_name = type_name.trim_end_matches("[]");
+    let valid = type_name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && type_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !valid {
+        return Err(TycoError::Schema(format!(
+            "Invalid type '{declaration}' for '{name}'"
+        )));
+    }
+    let mut field = FieldSchema::new(name, type_name);
+    field.is_primary_key = is_primary_key;
+    field.is_nullable = is_nullable;
+    field.is_array = is_array;
+    Ok(field)
+}
+
+/// Checks `value` against the declared type of `field`, converting values
+/// with an unambiguous conversion: integers to floats and between `int` and
+/// `uint`, and strings written as literals of the declared scalar type
+/// (`"2024-01-31"` for a date).
+pub(crate) fn coerce(value: TycoValue, field: &FieldSchema, name: &str) -> Result<TycoValue, TycoError> {
+    let mismatch = |expected: String, found: &TycoValue| TycoError::TypeMismatch {
+        field: Some(name.to_string()),
+        expected,
+        found: found.type_name().to_string(),
+    };
+    match value {
+        TycoValue::Null if field.is_nullable => Ok(TycoValue::Null),
+        TycoValue::Array(items) if field.is_array => items
+            .into_iter()
+            .map(|item| {
+                coerce_item(&item, &field.type_name)
+                    .ok_or_else(|| mismatch(field.type_name.clone(), &item))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(TycoValue::Array),
+        value if !field.is_array => coerce_item(&value, &field.type_name)
+            .ok_or_else(|| mismatch(describe_type(field), &value)),
+        value => Err(mismatch(describe_type(field), &value)),
+    }
+}
+
+fn coerce_item(value: &TycoValue, type_name: &str) -> Option<TycoValue> {
+    let coerced = match (type_name, value) {
+        ("str", TycoValue::String(_))
+        | ("bool", TycoValue::Bool(_))
+        | ("int", TycoValue::Int(_))
+        | ("uint" | "u64", TycoValue::UInt(_))
+        | ("float", TycoValue::Float(_))
+        | ("decimal", TycoValue::Decimal(_))
+        | ("date", TycoValue::Date(_))
+        | ("time", TycoValue::Time(_))
+        | ("datetime", TycoValue::DateTime(_))
+        | ("bytes", TycoValue::Bytes(_)) => value.clone(),
+        #[cfg(feature = "bigint")]
+        ("bigint", TycoValue::BigInt(_)) => value.clone(),
+        (_, TycoValue::String(s)) if type_name != "str" => {
+            parse_scalar(&s.value, type_name)?.ok()?
+        }
+        ("int", _) => TycoValue::Int(value.as_i64()?),
+        ("uint" | "u64", _) => TycoValue::UInt(value.as_u64()?),
+        ("float", TycoValue::Int(_) | TycoValue::UInt(_)) => TycoValue::Float(value.as_f64()?),
+        (_, TycoValue::Reference(reference)) if reference.struct_name == type_name => value.clone(),
+        (_, TycoValue::Instance(instance)) if instance.struct_name() == type_name => value.clone(),
+        _ => return None,
+    };
+    Some(coerced)
+}
+
+pub(crate) fn infer_type(value: &TycoValue) -> Option<String> {
+    match value {
+        TycoValue::Null => None,
+        TycoValue::Array(items) => Some(format!("{}[]", infer_type(items.first()?)?)),
+        value => Some(value.type_name().to_string()),
+    }
+}
+
+fn describe_type(field: &FieldSchema) -> String {
+    format!(
+        "{}{}{}",
+        if field.is_nullable { "?" } else { "" },
+        field.type_name,
+        if field.is_array { "[]" } else { "" }
+    )
+}
@@ -1,11 +1,33 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    fmt,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
 
 use indexmap::IndexMap;
 use serde_json::Value as JsonValue;
 
-use crate::{error::TycoError, value::TycoValue, value::TycoInstance};
+#[cfg(feature = "bigint")]
+use crate::numeric::parse_big_integer;
+use crate::{
+    builder::{check_field_types, check_required_fields, ContextBuilder},
+    diff::{apply_change, same_value, Change},
+    error::{Diagnostic, SourceSpan, TycoError},
+    loader::{merge_source_files, SourceFile},
+    numeric::{parse_decimal, parse_float, parse_integer, parse_unsigned},
+    parser::{
+        field_type_name, is_append, parse_override_value, parse_reference_key, parse_scalar,
+        RedefinitionPolicy,
+    },
+    path::{self, parse_path, split_qualified_name, PathMatch, PathSegment},
+    schema,
+    utils::parse_bytes_literal,
+    value::{struct_instance_json, JsonOptions, TycoInstance, TycoReference, TycoValue},
+    writer,
+};
 
 #[derive(Clone, Debug)]
 pub struct FieldSchema {
@@ -30,13 +52,179 @@ impl FieldSchema {
     }
 }
 
+/// Primary key of an instance: one value per `*` field, in schema order.
+///
+/// Keys built from text (a reference argument, or `"80"` passed to
+/// `find_by_primary_key`) are parsed with the key field's declared type before
+/// lookup, so `Port(0x50)` matches an `int` key of 80.
+#[derive(Clone, Debug, Default)]
+pub struct PrimaryKey(Vec<TycoValue>);
+
+impl PrimaryKey {
+    pub fn new(parts: Vec<TycoValue>) -> Self {
+        Self(parts)
+    }
+
+    pub fn parts(&self) -> &[TycoValue] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn canonical(&self) -> Vec<KeyPart<'_>> {
+        self.0.iter().map(KeyPart::from).collect()
+    }
+}
+
+/// Hashable view of a key value; numbers compare by value and floats by bits.
+#[derive(Eq, Hash, PartialEq)]
+enum KeyPart<'a> {
+    Null,
+    Bool(bool),
+    Int(i128),
+    #[cfg(feature = "bigint")]
+    BigInt(&'a num_bigint::BigInt),
+    Float(u64),
+    Decimal(String),
+    Str(&'a str),
+    Date(&'a str),
+    Time(&'a str),
+    DateTime(&'a str),
+    Bytes(&'a [u8]),
+    Other(String),
+}
+
+impl<'a> From<&'a TycoValue> for KeyPart<'a> {
+    fn from(value: &'a TycoValue) -> Self {
+        match value {
+            TycoValue::Null => KeyPart::Null,
+            TycoValue::Bool(v) => KeyPart::Bool(*v),
+            TycoValue::Int(v) => KeyPart::Int(i128::from(*v)),
+            TycoValue::UInt(v) => KeyPart::Int(i128::from(*v)),
+            #[cfg(feature = "bigint")]
+            TycoValue::BigInt(v) => KeyPart::BigInt(v),
+            TycoValue::Float(v) => KeyPart::Float(if *v == 0.0 { 0 } else { v.to_bits() }),
+            TycoValue::Decimal(v) => KeyPart::Decimal(normalize_decimal(v)),
+            TycoValue::String(s) => KeyPart::Str(&s.value),
+            TycoValue::Date(v) => KeyPart::Date(v),
+            TycoValue::Time(v) => KeyPart::Time(v),
+            TycoValue::DateTime(v) => KeyPart::DateTime(v),
+            TycoValue::Bytes(v) => KeyPart::Bytes(v),
+            other => KeyPart::Other(other.to_template_text()),
+        }
+    }
+}
+
+fn normalize_decimal(literal: &str) -> String {
+    if literal.contains(['e', 'E']) || !literal.contains('.') {
+        return literal.to_string();
+    }
+    let trimmed = literal.trim_end_matches('0').trim_end_matches('.');
+    if trimmed == "-0" {
+        "0".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+impl PartialEq for PrimaryKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical() == other.canonical()
+    }
+}
+
+impl Eq for PrimaryKey {}
+
+impl Hash for PrimaryKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical().hash(state);
+    }
+}
+
+/// Writes the key as it appears in a reference or path, `us-east, web-01`,
+/// quoting parts that would not read back on their own.
+impl fmt::Display for PrimaryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = self.0.iter().map(key_part_text).collect::<Vec<_>>();
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// A key part as `parse_reference_key` reads it: plain text unless it has
+/// surrounding whitespace, a separator, a bracket or a quote.
+fn key_part_text(part: &TycoValue) -> String {
+    let text = part.to_template_text();
+    let special = ['"', '\'', ',', '[', ']', '(', ')', '{', '}', '\n'];
+    if !text.is_empty() && text.trim() == text && !text.contains(special) {
+        return text;
+    }
+    if !text.contains(['\'', '\n']) && !text.ends_with('\\') {
+        return format!("'{text}'");
+    }
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+impl From<&str> for PrimaryKey {
+    fn from(value: &str) -> Self {
+        Self(vec![TycoValue::from(value)])
+    }
+}
+
+impl From<String> for PrimaryKey {
+    fn from(value: String) -> Self {
+        Self(vec![TycoValue::from(value.as_str())])
+    }
+}
+
+impl From<i64> for PrimaryKey {
+    fn from(value: i64) -> Self {
+        Self(vec![TycoValue::Int(value)])
+    }
+}
+
+impl From<TycoValue> for PrimaryKey {
+    fn from(value: TycoValue) -> Self {
+        Self(vec![value])
+    }
+}
+
+impl From<Vec<TycoValue>> for PrimaryKey {
+    fn from(value: Vec<TycoValue>) -> Self {
+        Self(value)
+    }
+}
+
+impl<const N: usize> From<[&str; N]> for PrimaryKey {
+    fn from(value: [&str; N]) -> Self {
+        Self(value.iter().map(|part| TycoValue::from(*part)).collect())
+    }
+}
+
+impl From<&PrimaryKey> for PrimaryKey {
+    fn from(value: &PrimaryKey) -> Self {
+        value.clone()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TycoStruct {
     name: String,
     fields: Vec<FieldSchema>,
-    primary_key_field: Option<String>,
+    primary_key_fields: Vec<String>,
     instances: Vec<TycoInstance>,
-    primary_index: HashMap<String, TycoInstance>,
+    primary_index: HashMap<PrimaryKey, usize>,
+    /// Where each field default was written.
+    default_origins: HashMap<String, ValueOrigin>,
 }
 
 impl TycoStruct {
@@ -44,9 +232,10 @@ impl TycoStruct {
         Self {
             name: name.into(),
             fields: Vec::new(),
-            primary_key_field: None,
+            primary_key_fields: Vec::new(),
             instances: Vec::new(),
             primary_index: HashMap::new(),
+            default_origins: HashMap::new(),
         }
     }
 
@@ -62,8 +251,13 @@ impl TycoStruct {
         &mut self.fields
     }
 
+    /// First primary key field; composite keys are listed by `primary_key_fields`.
     pub fn primary_key_field(&self) -> Option<&str> {
-        self.primary_key_field.as_deref()
+        self.primary_key_fields.first().map(String::as_str)
+    }
+
+    pub fn primary_key_fields(&self) -> &[String] {
+        &self.primary_key_fields
     }
 
     pub fn instances(&self) -> &Vec<TycoInstance> {
@@ -74,9 +268,21 @@ impl TycoStruct {
         &mut self.instances
     }
 
+    /// Adds a field, or replaces the field of the same name in place when the
+    /// struct is declared again (for example by an included file).
     pub fn add_field(&mut self, field: FieldSchema) {
+        if let Some(existing) = self.fields.iter_mut().find(|f| f.name == field.name) {
+            *existing = field;
+            self.primary_key_fields = self
+                .fields
+                .iter()
+                .filter(|f| f.is_primary_key)
+                .map(|f| f.name.clone())
+                .collect();
+            return;
+        }
         if field.is_primary_key {
-            self.primary_key_field = Some(field.name.clone());
+            self.primary_key_fields.push(field.name.clone());
         }
         self.fields.push(field);
     }
@@ -85,8 +291,74 @@ impl TycoStruct {
         self.instances.push(instance);
     }
 
+    /// Drops a field from the schema and from every instance.
+    pub(crate) fn remove_field(&mut self, name: &str) -> Option<FieldSchema> {
+        let position = self.fields.iter().position(|f| f.name == name)?;
+        let removed = self.fields.remove(position);
+        self.primary_key_fields.retain(|field| field != name);
+        self.default_origins.remove(name);
+        for instance in &mut self.instances {
+            instance.remove_attribute(name);
+        }
+        Some(removed)
+    }
+
     pub fn has_primary_key(&self) -> bool {
-        self.primary_key_field.is_some()
+        !self.primary_key_fields.is_empty()
+    }
+
+    pub fn primary_key_of(&self, instance: &TycoInstance) -> Option<PrimaryKey> {
+        if !self.has_primary_key() {
+            return None;
+        }
+        self.primary_key_fields
+            .iter()
+            .map(|field| instance.get_attribute(field).cloned())
+            .collect::<Option<Vec<_>>>()
+            .map(PrimaryKey)
+    }
+
+    /// Checks the arity of `key` and parses any textual parts with the
+    /// declared type of the matching primary key field.
+    pub fn normalize_key(&self, key: &PrimaryKey) -> Result<PrimaryKey, TycoError> {
+        self.check_key_arity(key)?;
+        let mut parts = Vec::with_capacity(key.len());
+        for (part, field_name) in key.parts().iter().zip(&self.primary_key_fields) {
+            let type_name = self
+                .fields
+                .iter()
+                .find(|field| &field.name == field_name)
+                .map_or("str", |field| field.type_name.as_str());
+            let typed = match part {
+                TycoValue::String(s) if type_name != "str" => {
+                    match parse_scalar(&s.value, type_name) {
+                        Some(result) => result?,
+                        None => part.clone(),
+                    }
+                }
+                other => other.clone(),
+            };
+            parts.push(typed);
+        }
+        Ok(PrimaryKey(parts))
+    }
+
+    pub fn check_key_arity(&self, key: &PrimaryKey) -> Result<(), TycoError> {
+        if key.len() == self.primary_key_fields.len() {
+            return Ok(());
+        }
+        Err(TycoError::Reference(format!(
+            "{}({key}) has {} key value(s) but {} is keyed by {} field(s): {}",
+            self.name,
+            key.len(),
+            self.name,
+            self.primary_key_fields.len(),
+            self.primary_key_fields.join(", ")
+        )))
+    }
+
+    pub(crate) fn set_default_origin(&mut self, field_name: String, origin: ValueOrigin) {
+        self.default_origins.insert(field_name, origin);
     }
 
     pub fn set_default(
@@ -105,42 +377,302 @@ impl TycoStruct {
 
     pub fn build_primary_index(&mut self) -> Result<(), TycoError> {
         self.primary_index.clear();
-        let Some(pk_field) = &self.primary_key_field else {
-            return Ok(());
-        };
-
-        for instance in &self.instances {
-            if let Some(value) = instance.get_attribute(pk_field) {
-                self.primary_index
-                    .insert(value.to_template_text(), instance.clone());
+        for (index, instance) in self.instances.iter().enumerate() {
+            if let Some(key) = self.primary_key_of(instance) {
+                self.primary_index.insert(key, index);
             }
         }
         Ok(())
     }
 
-    pub fn find_by_primary_key(&self, key: &str) -> Option<&TycoInstance> {
-        self.primary_index.get(key)
+    pub fn find_by_primary_key(&self, key: impl Into<PrimaryKey>) -> Option<&TycoInstance> {
+        self.position_by_primary_key(key)
+            .and_then(|index| self.instances.get(index))
+    }
+
+    /// Path used to address an instance: `Host[prod-01-us]` for keyed structs
+    /// and `Application[0]` otherwise.
+    pub fn instance_path(&self, index: usize, instance: &TycoInstance) -> String {
+        match self.primary_key_of(instance) {
+            Some(key) => format!("{}[{key}]", self.name),
+            None => format!("{}[{index}]", self.name),
+        }
+    }
+
+    /// Resolves the `[...]` part of a path: a primary key (`prod-01`,
+    /// `us-east, web-01`, `"a, b"`) for keyed structs, written like the
+    /// arguments of a reference, or a position otherwise.
+    pub(crate) fn index_for_path_key(&self, key: &str) -> Option<usize> {
+        if self.has_primary_key() {
+            self.position_by_primary_key(parse_reference_key(key).ok()?)
+        } else {
+            key.parse::<usize>()
+                .ok()
+                .filter(|index| *index < self.instances.len())
+        }
+    }
+
+    /// Index into `instances()` of the instance with the given key.
+    pub fn position_by_primary_key(&self, key: impl Into<PrimaryKey>) -> Option<usize> {
+        let key = self.normalize_key(&key.into()).ok()?;
+        self.primary_index.get(&key).copied()
     }
 }
 
-#[derive(Clone, Debug)]
+/// Converts an untyped inline-instance argument to its field's type.
+fn coerce_value(value: TycoValue, schema: &FieldSchema) -> Result<TycoValue, TycoError> {
+    if schema.is_array {
+        return Ok(value);
+    }
+    // Templates are converted once rendered; see `coerce_rendered_templates`.
+    match (schema.type_name.as_str(), value) {
+        (_, TycoValue::String(s)) if s.has_template => Ok(TycoValue::String(s)),
+        ("int", TycoValue::String(s)) => parse_integer(&s.value).map(TycoValue::Int),
+        ("uint" | "u64", TycoValue::String(s)) => {
+            parse_unsigned(&s.value).map(TycoValue::UInt)
+        }
+        #[cfg(feature = "bigint")]
+        ("bigint", TycoValue::String(s)) => {
+            parse_big_integer(&s.value).map(TycoValue::BigInt)
+        }
+        ("decimal", TycoValue::String(s)) => parse_decimal(&s.value).map(TycoValue::Decimal),
+        ("float", TycoValue::String(s)) => parse_float(&s.value).map(TycoValue::Float),
+        ("bytes", TycoValue::String(s)) => {
+            parse_bytes_literal(&s.value).map(TycoValue::Bytes)
+        }
+        ("bool", TycoValue::String(s)) => Ok(TycoValue::Bool(matches!(
+            s.value.as_str(),
+            "true" | "True"
+        ))),
+        (_, other) => Ok(other),
+    }
+}
+
+/// Byte offset of the `=` separating path and value in an override, skipping
+/// any `=` inside the `[...]` of a primary key.
+fn find_assignment(assignment: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (idx, ch) in assignment.char_indices() {
+        match ch {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            '=' if depth == 0 => return Some(idx),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The value a layer leaves behind: `value` itself, or `base` with the items
+/// of `value` appended when the layer wrote the array as `+[...]`.
+fn merge_layer_value(base: Option<TycoValue>, value: TycoValue, append: bool) -> TycoValue {
+    match (base, value) {
+        (Some(TycoValue::Array(mut items)), TycoValue::Array(extra)) if append => {
+            items.extend(extra);
+            TycoValue::Array(items)
+        }
+        (_, value) => value,
+    }
+}
+
+fn qualify_value(value: &mut TycoValue, alias: &str) {
+    match value {
+        TycoValue::Reference(reference) => {
+            reference.struct_name = format!("{alias}.{}", reference.struct_name);
+        }
+        TycoValue::Instance(instance) => qualify_instance(instance, alias),
+        TycoValue::Array(items) => {
+            for item in items {
+                qualify_value(item, alias);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn qualify_instance(instance: &mut TycoInstance, alias: &str) {
+    let struct_name = format!("{alias}.{}", instance.struct_name());
+    instance.set_struct_name(struct_name);
+    for value in instance.attributes_mut().values_mut() {
+        qualify_value(value, alias);
+    }
+}
+
+fn escape_depfile_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for ch in path.chars() {
+        match ch {
+            ' ' | '#' => {
+                escaped.push('\\');
+                escaped.push(ch);
+            }
+            '$' => escaped.push_str("$$"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// The top-level item a path starts from.
+pub(crate) enum PathRoot {
+    Global(String),
+    /// The default of a struct field, e.g. `Host.cores`.
+    Default { struct_name: String, field: String },
+    Field {
+        struct_name: String,
+        index: usize,
+        field: String,
+    },
+}
+
+/// Struct position in `TycoContext::structs` and instance position within it.
+type InstanceId = (usize, usize);
+
+#[derive(Clone, Debug, Default)]
 pub struct TycoContext {
     globals: IndexMap<String, TycoValue>,
     structs: IndexMap<String, TycoStruct>,
+    referrers: HashMap<(String, usize), Vec<Referrer>>,
+    used_globals: HashSet<String>,
+    source_files: Vec<SourceFile>,
+    source_dirs: Vec<PathBuf>,
+    diagnostics: Vec<Diagnostic>,
+    /// Paths of values written as `+[...]`, which append to the array of an
+    /// earlier layer instead of replacing it.
+    appends: HashSet<String>,
+    /// The overlay layer that last set each value; absent means the base.
+    layer_origins: HashMap<String, usize>,
+    layers: usize,
+    /// Where each global was declared.
+    global_origins: HashMap<String, ValueOrigin>,
+    /// The declared type of each global, e.g. `int`, `?str` or `Host[]`.
+    global_types: HashMap<String, String>,
+}
+
+/// Where a reference was found: a global or an instance of a struct.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReferenceOwner {
+    Global(String),
+    Instance { struct_name: String, index: usize },
+}
+
+/// A value that references an instance, as returned by `TycoContext::referrers_of`.
+#[derive(Clone, Debug)]
+pub struct Referrer {
+    pub owner: ReferenceOwner,
+    /// Path of the referencing value, e.g. `Application[0].host` or `backups[1]`.
+    pub path: String,
+}
+
+impl Referrer {
+    /// The referring instance, or `None` when the reference lives in a global.
+    pub fn instance<'a>(&self, ctx: &'a TycoContext) -> Option<&'a TycoInstance> {
+        match &self.owner {
+            ReferenceOwner::Global(_) => None,
+            ReferenceOwner::Instance { struct_name, index } => {
+                ctx.get_struct(struct_name)?.instances().get(*index)
+            }
+        }
+    }
+}
+
+/// How a value came to have its current content; see `TycoContext::explain`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OriginKind {
+    /// Taken from the struct field's default.
+    Default,
+    /// Written on the instance as `field: value`, or declared as a global.
+    Explicit,
+    /// Written on the instance as a positional argument.
+    Positional,
+    /// Rendered from a template with `{...}` placeholders.
+    Templated,
+    /// Set by an overlay layer.
+    Overlay,
+    /// Set by `TycoContext::apply_override`.
+    Override,
+}
+
+/// Where a value was written. `span` points at the value in its source file,
+/// or at the struct default for `OriginKind::Default`.
+#[derive(Clone, Debug)]
+pub struct ValueOrigin {
+    pub kind: OriginKind,
+    pub span: Option<SourceSpan>,
+    /// The overlay layer the value came from; `0` for the base.
+    pub layer: usize,
+    /// The unrendered text of templated strings.
+    pub template: Option<String>,
+}
+
+impl ValueOrigin {
+    pub(crate) fn new(kind: OriginKind, span: SourceSpan) -> Self {
+        Self {
+            kind,
+            span: Some(span),
+            layer: 0,
+            template: None,
+        }
+    }
+}
+
+/// An instance of a primary-keyed struct that nothing references.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnusedInstance {
+    pub struct_name: String,
+    pub index: usize,
+    pub key: PrimaryKey,
+    /// Path of the instance, e.g. `Host[prod-01]`.
+    pub path: String,
 }
 
 impl TycoContext {
+    /// Starts building a context in code; see `ContextBuilder`.
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::new()
+    }
+
     pub fn new() -> Self {
         Self {
             globals: IndexMap::new(),
             structs: IndexMap::new(),
+            referrers: HashMap::new(),
+            used_globals: HashSet::new(),
+            source_files: Vec::new(),
+            source_dirs: Vec::new(),
+            diagnostics: Vec::new(),
+            appends: HashSet::new(),
+            layer_origins: HashMap::new(),
+            layers: 0,
+            global_origins: HashMap::new(),
+            global_types: HashMap::new(),
         }
     }
 
+    /// Sets a global, replacing any earlier value without checking it; see
+    /// `try_set_global` to apply a `RedefinitionPolicy`.
     pub fn set_global(&mut self, name: impl Into<String>, value: TycoValue) {
         self.globals.insert(name.into(), value);
     }
 
+    /// Sets a global, applying `policy` when it already holds a different
+    /// value, as the parser does for globals declared twice.
+    pub fn try_set_global(
+        &mut self,
+        name: impl Into<String>,
+        value: TycoValue,
+        policy: RedefinitionPolicy,
+    ) -> Result<(), TycoError> {
+        let name = name.into();
+        if let Some(existing) = self.globals.get(&name) {
+            if !same_value(existing, &value) {
+                self.redefine(format!("Global '{name}' is already defined"), policy)?;
+            }
+        }
+        self.set_global(name, value);
+        Ok(())
+    }
+
     pub fn globals(&self) -> &IndexMap<String, TycoValue> {
         &self.globals
     }
@@ -149,6 +681,8 @@ impl TycoContext {
         &mut self.globals
     }
 
+    /// Adds a struct, replacing any struct of the same name with its fields
+    /// and instances; see `try_add_struct` to apply a `RedefinitionPolicy`.
     pub fn add_struct(&mut self, tyco_struct: TycoStruct) {
         self.structs
             .entry(tyco_struct.name().to_string())
@@ -158,6 +692,36 @@ impl TycoContext {
             .or_insert(tyco_struct);
     }
 
+    /// Adds a struct, applying `policy` when a struct of the same name is
+    /// already defined.
+    pub fn try_add_struct(
+        &mut self,
+        tyco_struct: TycoStruct,
+        policy: RedefinitionPolicy,
+    ) -> Result<(), TycoError> {
+        if self.structs.contains_key(tyco_struct.name()) {
+            self.redefine(
+                format!("Struct '{}' is already defined", tyco_struct.name()),
+                policy,
+            )?;
+        }
+        self.add_struct(tyco_struct);
+        Ok(())
+    }
+
+    fn redefine(&mut self, message: String, policy: RedefinitionPolicy) -> Result<(), TycoError> {
+        match policy {
+            RedefinitionPolicy::Error => return Err(TycoError::Schema(message)),
+            RedefinitionPolicy::Warn => self.push_diagnostic(Diagnostic {
+                message,
+                span: None,
+                previous: None,
+            }),
+            RedefinitionPolicy::Override => {}
+        }
+        Ok(())
+    }
+
     pub fn structs(&self) -> &IndexMap<String, TycoStruct> {
         &self.structs
     }
@@ -174,42 +738,732 @@ impl TycoContext {
         self.structs.get_mut(name)
     }
 
+    /// Files read by `TycoParser::parse_file`, starting with the parsed file
+    /// and followed by its includes in the order they were read. Empty for
+    /// contexts parsed from a string.
+    pub fn source_files(&self) -> &[SourceFile] {
+        &self.source_files
+    }
+
+    /// Directories listed by glob and directory includes, whose contents
+    /// decide which files a parse reads.
+    pub fn source_dirs(&self) -> &[PathBuf] {
+        &self.source_dirs
+    }
+
+    pub(crate) fn set_source_files(&mut self, files: Vec<SourceFile>, dirs: Vec<PathBuf>) {
+        self.source_files = files;
+        self.source_dirs = dirs;
+    }
+
+    /// Warnings collected while parsing, in the order they were found.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub(crate) fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// A Makefile/ninja depfile rule making `target` depend on every file in
+    /// `source_files` and every directory in `source_dirs`, so adding a file
+    /// that a glob include would match also triggers a rebuild.
+    pub fn depfile(&self, target: &str) -> String {
+        let mut rule = escape_depfile_path(target);
+        rule.push(':');
+        let paths = self.source_files.iter().map(|file| &file.path);
+        for path in paths.chain(&self.source_dirs) {
+            rule.push(' ');
+            rule.push_str(&escape_depfile_path(&path.to_string_lossy()));
+        }
+        rule.push('\n');
+        rule
+    }
+
+    /// Adds the globals and structs of a separately parsed file under
+    /// `alias`, so its `Port` becomes `net.Port` and its `timezone` becomes
+    /// `net.timezone`. References inside the imported file are rewritten to
+    /// the qualified names.
+    pub(crate) fn import_namespace(&mut self, alias: &str, imported: TycoContext) {
+        let struct_names = imported.structs.keys().cloned().collect::<HashSet<_>>();
+        self.diagnostics.extend(imported.diagnostics);
+        for (name, origin) in imported.global_origins {
+            self.global_origins.insert(format!("{alias}.{name}"), origin);
+        }
+        for (name, type_name) in imported.global_types {
+            let (marker, declared) = type_name
+                .strip_prefix('?')
+                .map_or(("", type_name.as_str()), |declared| ("?", declared));
+            let (base, suffix) = declared
+                .strip_suffix("[]")
+                .map_or((declared, ""), |base| (base, "[]"));
+            let type_name = if struct_names.contains(base) {
+                format!("{marker}{alias}.{base}{suffix}")
+            } else {
+                type_name.clone()
+            };
+            self.global_types.insert(format!("{alias}.{name}"), type_name);
+        }
+        for (name, mut value) in imported.globals {
+            qualify_value(&mut value, alias);
+            self.globals.insert(format!("{alias}.{name}"), value);
+        }
+        for (name, mut struct_def) in imported.structs {
+            struct_def.name = format!("{alias}.{name}");
+            for field in &mut struct_def.fields {
+                if struct_names.contains(&field.type_name) {
+                    field.type_name = format!("{alias}.{}", field.type_name);
+                }
+                if let Some(default) = &mut field.default_value {
+                    qualify_value(default, alias);
+                }
+            }
+            for instance in &mut struct_def.instances {
+                qualify_instance(instance, alias);
+            }
+            self.structs.insert(struct_def.name.clone(), struct_def);
+        }
+    }
+
+    pub(crate) fn set_global_origin(&mut self, name: String, origin: ValueOrigin) {
+        self.global_origins.insert(name, origin);
+    }
+
+    /// The declared type of a global, e.g. `str` or `int[]`.
+    pub(crate) fn global_type(&self, name: &str) -> Option<&str> {
+        self.global_types.get(name).map(String::as_str)
+    }
+
+    pub(crate) fn set_global_type(&mut self, name: String, type_name: String) {
+        self.global_types.insert(name, type_name);
+    }
+
+    pub(crate) fn mark_append(&mut self, path: String) {
+        self.appends.insert(path);
+    }
+
+    /// Applies `other` on top of this context and renders the result:
+    ///
+    /// - globals in `other` replace globals of the same name;
+    /// - struct fields declared in `other` with a default update the default,
+    ///   which every instance that does not set the field picks up;
+    /// - instances are merged by primary key, with the fields written in
+    ///   `other` replacing the existing ones; new keys and instances of
+    ///   structs without a primary key are appended;
+    /// - arrays are replaced, unless the overlay wrote them as `+[...]`, in
+    ///   which case the items are appended.
+    ///
+    /// Templates are rendered again afterwards, so `{name}` placeholders see
+    /// the overridden values. `layer_of` reports which layer won.
+    pub fn overlay(&mut self, other: &TycoContext) -> Result<(), TycoError> {
+        let layer = self.layers + 1;
+        self.merge_layer(other.clone(), layer);
+        self.render()?;
+        self.record_default_layers();
+        Ok(())
+    }
+
+    /// The layer that set the value at `path` (`timezone`,
+    /// `Host[prod-01].cores`, or `Host.cores` for a struct default): `0` for
+    /// the base, `n` for the n-th overlay, and `None` when nothing is set
+    /// there.
+    pub fn layer_of(&self, path: &str) -> Option<usize> {
+        if let Some(&layer) = self.layer_origins.get(path) {
+            return Some(layer);
+        }
+        self.get(path).map(|_| 0)
+    }
+
+    /// A copy of the struct schemas without instances or defaults, used to
+    /// parse an overlay file against the structs of earlier layers.
+    pub(crate) fn schema_only(&self) -> TycoContext {
+        let mut schema = TycoContext::new();
+        for (name, struct_def) in &self.structs {
+            let mut struct_def = TycoStruct {
+                instances: Vec::new(),
+                primary_index: HashMap::new(),
+                ..struct_def.clone()
+            };
+            for field in &mut struct_def.fields {
+                field.default_value = None;
+            }
+            schema.structs.insert(name.clone(), struct_def);
+        }
+        schema
+    }
+
+    /// Merges an unrendered or rendered layer into this context without
+    /// rendering; see `overlay` for the semantics.
+    pub(crate) fn merge_layer(&mut self, layer: TycoContext, index: usize) {
+        self.layers = self.layers.max(index);
+        let appends = layer.appends;
+        let mut set_by_layer = Vec::new();
+        self.global_origins.extend(layer.global_origins);
+        self.global_types.extend(layer.global_types);
+
+        for (name, value) in layer.globals {
+            let base = self.globals.get(&name).cloned();
+            let value = merge_layer_value(base, value, appends.contains(&name));
+            self.globals.insert(name.clone(), value);
+            set_by_layer.push(name);
+        }
+
+        for (struct_name, layer_struct) in layer.structs {
+            let Some(base) = self.structs.get_mut(&struct_name) else {
+                for field in layer_struct.fields() {
+                    if field.default_value.is_some() {
+                        set_by_layer.push(format!("{struct_name}.{}", field.name));
+                    }
+                }
+                for (idx, instance) in layer_struct.instances().iter().enumerate() {
+                    let path = layer_struct.instance_path(idx, instance);
+                    for field in instance.field_order() {
+                        set_by_layer.push(format!("{path}.{field}"));
+                    }
+                }
+                self.structs.insert(struct_name, layer_struct);
+                continue;
+            };
+
+            for field in layer_struct.fields() {
+                let path = format!("{struct_name}.{}", field.name);
+                let existing = base.fields.iter_mut().find(|f| f.name == field.name);
+                match existing {
+                    Some(existing)
+                        if existing.type_name == field.type_name
+                            && existing.is_array == field.is_array
+                            && existing.is_nullable == field.is_nullable
+                            && existing.is_primary_key == field.is_primary_key =>
+                    {
+                        if let Some(default) = &field.default_value {
+                            existing.default_value = Some(merge_layer_value(
+                                existing.default_value.take(),
+                                default.clone(),
+                                appends.contains(&path),
+                            ));
+                            set_by_layer.push(path);
+                        }
+                    }
+                    _ => {
+                        if field.default_value.is_some() {
+                            set_by_layer.push(path);
+                        }
+                        base.add_field(field.clone());
+                    }
+                }
+                if field.default_value.is_some() {
+                    if let Some(origin) = layer_struct.default_origins.get(&field.name) {
+                        base.default_origins
+                            .insert(field.name.clone(), origin.clone());
+                    }
+                }
+            }
+
+            for (idx, instance) in layer_struct.instances().iter().enumerate() {
+                let layer_path = layer_struct.instance_path(idx, instance);
+                let key = layer_struct.primary_key_of(instance);
+                let position = key.as_ref().and_then(|key| {
+                    base.instances
+                        .iter()
+                        .position(|existing| base.primary_key_of(existing).as_ref() == Some(key))
+                });
+                let Some(position) = position else {
+                    let path = base.instance_path(base.instances.len(), instance);
+                    for field in instance.field_order() {
+                        if !instance.is_default(field) {
+                            set_by_layer.push(format!("{path}.{field}"));
+                        }
+                    }
+                    let mut instance = instance.clone();
+                    instance.reset_rendering();
+                    base.add_instance(instance);
+                    continue;
+                };
+                let path = base.instance_path(position, &base.instances[position]);
+                let target = &mut base.instances[position];
+                for field in instance.field_order() {
+                    if instance.is_default(field) {
+                        continue;
+                    }
+                    let Some(value) = instance.get_attribute(field) else {
+                        continue;
+                    };
+                    let append = appends.contains(&format!("{layer_path}.{field}"));
+                    // An unrendered instance has no defaults filled in yet.
+                    let current = target.get_attribute(field).cloned().or_else(|| {
+                        base.fields
+                            .iter()
+                            .find(|f| f.name == *field)
+                            .and_then(|f| f.default_value.clone())
+                    });
+                    let value = merge_layer_value(current, value.clone(), append);
+                    target.set_attribute(field.clone(), value);
+                    if let Some(origin) = instance.origin(field) {
+                        target.set_origin(field.clone(), origin.clone());
+                    }
+                    set_by_layer.push(format!("{path}.{field}"));
+                }
+            }
+        }
+
+        merge_source_files(&mut self.source_files, layer.source_files);
+        for dir in layer.source_dirs {
+            if !self.source_dirs.contains(&dir) {
+                self.source_dirs.push(dir);
+            }
+        }
+        self.diagnostics.extend(layer.diagnostics);
+        if index > 0 {
+            for path in set_by_layer {
+                self.layer_origins.insert(path, index);
+            }
+        }
+    }
+
+    /// Attributes instance fields that took an overlaid struct default to the
+    /// layer that set the default.
+    pub(crate) fn record_default_layers(&mut self) {
+        for (struct_name, struct_def) in &self.structs {
+            for (idx, instance) in struct_def.instances().iter().enumerate() {
+                let path = struct_def.instance_path(idx, instance);
+                for field in instance.field_order() {
+                    if !instance.is_default(field) {
+                        continue;
+                    }
+                    let default_path = format!("{struct_name}.{field}");
+                    if let Some(&layer) = self.layer_origins.get(&default_path) {
+                        self.layer_origins.insert(format!("{path}.{field}"), layer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies an override written as `path=value`, such as `timezone=UTC`
+    /// or `Host[prod-01].cores=16`, the way launchers accept `--set`. The
+    /// path names a global, a struct default (`Host.cores`) or an instance
+    /// field, and the value is parsed with its declared type exactly as it
+    /// would be in a file; `+[...]` appends to an array. Templates are
+    /// rendered again afterwards. On error the context is left unchanged.
+    pub fn apply_override(&mut self, assignment: &str) -> Result<(), TycoError> {
+        self.apply_overrides([assignment])
+    }
+
+    /// Applies several `path=value` overrides in order, then renders once.
+    pub fn apply_overrides<I, S>(&mut self, assignments: I) -> Result<(), TycoError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut updated = self.clone();
+        for assignment in assignments {
+            updated.set_override(assignment.as_ref())?;
+        }
+        updated.render()?;
+        *self = updated;
+        Ok(())
+    }
+
+    fn set_override(&mut self, assignment: &str) -> Result<(), TycoError> {
+        let Some(split) = find_assignment(assignment) else {
+            return Err(TycoError::parse(format!(
+                "Override '{assignment}' must be written as path=value"
+            )));
+        };
+        let path = assignment[..split].trim();
+        let value = assignment[split + 1..].trim();
+        let value_start = assignment.len() - assignment[split + 1..].trim_start().len();
+        let span = SourceSpan {
+            path: None,
+            line: 1,
+            column: assignment[..value_start].chars().count() + 1,
+            line_text: assignment.to_string(),
+        };
+        let append = is_append(value);
+        let origin = ValueOrigin::new(OriginKind::Override, span.clone());
+
+        let segments = parse_path(path)?;
+        let root = match self.locate_root(&segments) {
+            Some((root, [])) => root,
+            Some(_) => {
+                return Err(TycoError::parse(format!(
+                    "Cannot override '{path}': only globals, struct defaults and instance \
+                     fields can be overridden"
+                )))
+            }
+            None => {
+                return Err(TycoError::parse(format!(
+                    "Cannot override '{path}': no such global or field"
+                )))
+            }
+        };
+
+        match root {
+            PathRoot::Global(name) => {
+                let Some(type_name) = self.global_types.get(&name) else {
+                    return Err(TycoError::parse(format!(
+                        "Cannot override '{path}': the global has no declared type"
+                    )));
+                };
+                let nullable = type_name.starts_with('?');
+                let parsed =
+                    parse_override_value(value, type_name.trim_start_matches('?'), self, &span)?;
+                if parsed.is_null() && !nullable {
+                    return Err(TycoError::parse_with_span(
+                        format!("Cannot override '{path}': the global is not nullable"),
+                        span,
+                    ));
+                }
+                let base = self.globals.get(&name).cloned();
+                self.globals
+                    .insert(name.clone(), merge_layer_value(base, parsed, append));
+                self.global_origins.insert(name, origin);
+            }
+            PathRoot::Default { struct_name, field } => {
+                let parsed = self.parse_field_override(&struct_name, &field, value, &span)?;
+                let struct_def = &mut self.structs[&struct_name];
+                if let Some(schema) = struct_def.fields.iter_mut().find(|f| f.name == field) {
+                    let base = schema.default_value.take();
+                    schema.default_value = Some(merge_layer_value(base, parsed, append));
+                }
+                struct_def.set_default_origin(field, origin);
+            }
+            PathRoot::Field {
+                struct_name,
+                index,
+                field,
+            } => {
+                let parsed = self.parse_field_override(&struct_name, &field, value, &span)?;
+                let instance = &mut self.structs[&struct_name].instances[index];
+                let base = instance.get_attribute(&field).cloned();
+                instance.set_attribute(field.clone(), merge_layer_value(base, parsed, append));
+                instance.set_origin(field, origin);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_field_override(
+        &self,
+        struct_name: &str,
+        field: &str,
+        value: &str,
+        span: &SourceSpan,
+    ) -> Result<TycoValue, TycoError> {
+        let schema = self.structs[struct_name]
+            .fields()
+            .iter()
+            .find(|f| f.name == field)
+            .ok_or_else(|| {
+                TycoError::parse(format!(
+                    "Cannot override '{struct_name}.{field}': the field is not declared"
+                ))
+            })?;
+        if schema.is_primary_key {
+            return Err(TycoError::parse(format!(
+                "Cannot override '{struct_name}.{field}': primary key fields cannot be overridden"
+            )));
+        }
+        let parsed = parse_override_value(value, &field_type_name(schema), self, span)?;
+        if parsed.is_null() && !schema.is_nullable {
+            return Err(TycoError::parse_with_span(
+                format!("Cannot override '{struct_name}.{field}': the field is not nullable"),
+                span.clone(),
+            ));
+        }
+        Ok(parsed)
+    }
+
+    /// Applies structured changes, as returned by `diff` or written by hand
+    /// (`Change::set`, `Change::InstanceAdded`, ...), in order. Each change
+    /// is checked against the schema: values are converted to their field's
+    /// type, added instances must set every required field and keep primary
+    /// keys unique, and added fields must have a known type. Templates are
+    /// rendered again afterwards. On error the context is left unchanged.
+    pub fn apply_patch(&mut self, changes: &[Change]) -> Result<(), TycoError> {
+        let mut patched = self.clone();
+        for change in changes {
+            apply_change(&mut patched, change)?;
+        }
+        check_field_types(&patched)?;
+        check_required_fields(&patched)?;
+        patched.render()?;
+        *self = patched;
+        Ok(())
+    }
+
+    /// The value or instance at `path`, e.g. `timezone`,
+    /// `Host[prod-01].cores` or `Application[web].host.os`; see `query` for
+    /// the path language. For paths selecting several values, the first.
+    pub fn get(&self, path: &str) -> Option<PathMatch<'_>> {
+        self.query(path).ok()?.into_iter().next()
+    }
+
+    /// Every value or instance selected by `path`, in document order:
+    ///
+    /// - `timezone` or `net.timezone` for a global;
+    /// - `Host[prod-01]` for an instance by primary key (`Host[us, web-01]`
+    ///   for composite keys, `Job[0]` for structs without one) and
+    ///   `Host.cores` for a struct default;
+    /// - `.field` for a field, following references to their targets, and
+    ///   `[1]` for an array item;
+    /// - `[*]` for every instance or array item, and
+    ///   `[?host.os == "Fedora"]` for those matching a condition, with
+    ///   `==`, `!=`, `<`, `<=`, `>` and `>=` comparing against a string,
+    ///   number, `true`, `false` or `null` (`[?field]` alone matches values
+    ///   that are neither null nor false).
+    ///
+    /// Fails only for malformed paths; paths that select nothing return an
+    /// empty list.
+    pub fn query(&self, path: &str) -> Result<Vec<PathMatch<'_>>, TycoError> {
+        Ok(path::query(self, &parse_path(path)?))
+    }
+
+    /// Explains where the value at `path` came from: the file, line and column
+    /// it was written at and whether it was written explicitly, positionally,
+    /// taken from a struct default, rendered from a template or set by an
+    /// overlay or `apply_override`. Overrides take precedence over overlays,
+    /// then templates, then how the value was written. Paths may follow
+    /// references (`Application[0].host.cores`) and index arrays
+    /// (`ports[1]`). Returns `None` when nothing is found at `path`.
+    pub fn explain(&self, path: &str) -> Option<ValueOrigin> {
+        let segments = parse_path(path).ok()?;
+        let (root, mut rest) = self.locate_root(&segments)?;
+        let (mut value, mut origin, mut canonical) = match root {
+            PathRoot::Global(name) => (
+                self.globals.get(&name)?,
+                self.global_origins.get(&name).cloned(),
+                name,
+            ),
+            PathRoot::Default { struct_name, field } => {
+                let struct_def = self.get_struct(&struct_name)?;
+                let schema = struct_def.fields().iter().find(|f| f.name == field)?;
+                (
+                    schema.default_value.as_ref()?,
+                    struct_def.default_origins.get(&field).cloned(),
+                    format!("{struct_name}.{field}"),
+                )
+            }
+            PathRoot::Field {
+                struct_name,
+                index,
+                field,
+            } => self.field_origin(&struct_name, index, &field)?,
+        };
+
+        while let Some((segment, tail)) = rest.split_first() {
+            rest = tail;
+            match (segment, value) {
+                (PathSegment::Index(index), TycoValue::Array(items)) => {
+                    value = items.get(index.parse::<usize>().ok()?)?;
+                }
+                (PathSegment::Name(field), TycoValue::Instance(instance)) => {
+                    if let Some(inner) = instance.origin(field) {
+                        origin = Some(inner.clone());
+                    }
+                    value = instance.get_attribute(field)?;
+                }
+                (PathSegment::Name(field), TycoValue::Reference(reference)) => {
+                    (value, origin, canonical) =
+                        self.field_origin(&reference.struct_name, self.resolve_position(reference)?, field)?;
+                }
+                _ => return None,
+            }
+        }
+
+        let mut origin = origin.unwrap_or(ValueOrigin {
+            kind: OriginKind::Explicit,
+            span: None,
+            layer: 0,
+            template: None,
+        });
+        if let TycoValue::String(s) = value {
+            origin.template = s.template().map(str::to_string);
+        }
+        origin.layer = self.layer_origins.get(&canonical).copied().unwrap_or(0);
+        if origin.kind != OriginKind::Override {
+            if origin.layer > 0 {
+                origin.kind = OriginKind::Overlay;
+            } else if origin.template.is_some() {
+                origin.kind = OriginKind::Templated;
+            }
+        }
+        Some(origin)
+    }
+
+    fn field_origin(
+        &self,
+        struct_name: &str,
+        index: usize,
+        field: &str,
+    ) -> Option<(&TycoValue, Option<ValueOrigin>, String)> {
+        let struct_def = self.get_struct(struct_name)?;
+        let instance = struct_def.instances().get(index)?;
+        let value = instance.get_attribute(field)?;
+        let origin = if instance.is_default(field) {
+            struct_def.default_origins.get(field)
+        } else {
+            instance.origin(field)
+        };
+        let path = format!("{}.{field}", struct_def.instance_path(index, instance));
+        Some((value, origin.cloned(), path))
+    }
+
+    /// Finds the global, struct default or instance field a path
+    /// starts with and returns it with the remaining segments. Names may
+    /// contain dots, as namespaced imports do (`net.Port[http]`).
+    pub(crate) fn locate_root<'p>(
+        &self,
+        segments: &'p [PathSegment],
+    ) -> Option<(PathRoot, &'p [PathSegment])> {
+        let (name, rest) = split_qualified_name(segments, |name| {
+            self.structs.contains_key(name) || self.globals.contains_key(name)
+        })?;
+        let Some(struct_def) = self.structs.get(&name) else {
+            return Some((PathRoot::Global(name), rest));
+        };
+        match rest {
+            [PathSegment::Index(key), PathSegment::Name(field), rest @ ..] => {
+                let index = struct_def.index_for_path_key(key)?;
+                struct_def.instances()[index].get_attribute(field)?;
+                Some((
+                    PathRoot::Field {
+                        struct_name: name,
+                        index,
+                        field: field.clone(),
+                    },
+                    rest,
+                ))
+            }
+            [PathSegment::Name(field), rest @ ..] => {
+                struct_def.fields().iter().find(|f| f.name == *field)?;
+                Some((
+                    PathRoot::Default {
+                        struct_name: name,
+                        field: field.clone(),
+                    },
+                    rest,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Follows a reference to the instance it points at.
+    pub fn resolve(&self, reference: &TycoReference) -> Option<&TycoInstance> {
+        self.get_struct(&reference.struct_name)?
+            .instances()
+            .get(self.resolve_position(reference)?)
+    }
+
+    /// Position of a reference's target in its struct. The position recorded
+    /// by `render` is only trusted while the instance there still has the
+    /// referenced key, so removing or reordering instances through
+    /// `instances_mut` does not leave references pointing at the wrong one.
+    pub(crate) fn resolve_position(&self, reference: &TycoReference) -> Option<usize> {
+        let struct_def = self.get_struct(&reference.struct_name)?;
+        let key = struct_def.normalize_key(&reference.primary_key).ok()?;
+        let has_key = |index: usize| {
+            struct_def
+                .instances()
+                .get(index)
+                .and_then(|instance| struct_def.primary_key_of(instance))
+                .is_some_and(|found| found == key)
+        };
+        match reference.resolved {
+            Some(index) if has_key(index) => Some(index),
+            _ => (0..struct_def.instances().len()).find(|&index| has_key(index)),
+        }
+    }
+
+    /// Resolves inline instances, references and templates. Rendering again
+    /// starts over from the templates and schema defaults, so it reflects any
+    /// changes made since the last render.
     pub fn render(&mut self) -> Result<(), TycoError> {
+        self.reset_rendering();
         self.resolve_inline_instances()?;
         for struct_def in self.structs_mut().values_mut() {
             struct_def.build_primary_index()?;
         }
         self.resolve_references()?;
-        self.render_templates();
-        Ok(())
+        let order = self.dependency_order()?;
+        self.render_templates(order);
+        self.coerce_rendered_templates()
     }
 
-    fn resolve_inline_instances(&mut self) -> Result<(), TycoError> {
-        let schema_snapshot = self.structs.clone();
+    /// Puts back templates and drops fields filled in from schema defaults.
+    pub(crate) fn reset_rendering(&mut self) {
+        for value in self.globals.values_mut() {
+            value.reset_rendering();
+        }
+        for struct_def in self.structs.values_mut() {
+            for instance in struct_def.instances_mut() {
+                instance.reset_rendering();
+            }
+        }
+    }
 
-        fn coerce_value(value: TycoValue, schema: &FieldSchema) -> Result<TycoValue, TycoError> {
-            if schema.is_array {
-                return Ok(value);
-            }
-            match (schema.type_name.as_str(), value) {
-                ("int", TycoValue::String(s)) => s
-                    .value
-                    .parse::<i64>()
-                    .map(TycoValue::Int)
-                    .map_err(|e| TycoError::parse(format!("Invalid int literal '{}': {e}", s.value))),
-                ("float", TycoValue::String(s)) => s
-                    .value
-                    .parse::<f64>()
-                    .map(TycoValue::Float)
-                    .map_err(|e| TycoError::parse(format!("Invalid float literal '{}': {e}", s.value))),
-                ("bool", TycoValue::String(s)) => Ok(TycoValue::Bool(matches!(
-                    s.value.as_str(),
-                    "true" | "True"
-                ))),
-                (_, other) => Ok(other),
+    /// Converts inline-instance arguments that were written as templates to
+    /// their field's type now that they are rendered. The template is kept
+    /// on the instance so the next render starts from it again.
+    fn coerce_rendered_templates(&mut self) -> Result<(), TycoError> {
+        fn coerce_in(
+            value: &mut TycoValue,
+            schemas: &HashMap<String, Vec<FieldSchema>>,
+        ) -> Result<(), TycoError> {
+            match value {
+                TycoValue::Array(items) => {
+                    for item in items {
+                        coerce_in(item, schemas)?;
+                    }
+                }
+                TycoValue::Instance(instance) => coerce_instance(instance, schemas)?,
+                _ => {}
             }
+            Ok(())
         }
 
+        fn coerce_instance(
+            instance: &mut TycoInstance,
+            schemas: &HashMap<String, Vec<FieldSchema>>,
+        ) -> Result<(), TycoError> {
+            if let Some(fields) = schemas.get(instance.struct_name()) {
+                for field in fields {
+                    let Some(TycoValue::String(s)) = instance.get_attribute(&field.name) else {
+                        continue;
+                    };
+                    if s.template().is_none() || field.type_name == "str" {
+                        continue;
+                    }
+                    let coerced = coerce_value(TycoValue::String(s.clone()), field)?;
+                    instance.set_rendered_attribute(&field.name, coerced);
+                }
+            }
+            for value in instance.attributes_mut().values_mut() {
+                coerce_in(value, schemas)?;
+            }
+            Ok(())
+        }
+
+        let schemas = self
+            .structs
+            .iter()
+            .map(|(name, struct_def)| (name.clone(), struct_def.fields().clone()))
+            .collect::<HashMap<_, _>>();
+        for value in self.globals.values_mut() {
+            coerce_in(value, &schemas)?;
+        }
+        for struct_def in self.structs.values_mut() {
+            for instance in struct_def.instances_mut() {
+                coerce_instance(instance, &schemas)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_inline_instances(&mut self) -> Result<(), TycoError> {
+        let schema_snapshot = self.structs.clone();
+
         fn resolve_value(
             value: &mut TycoValue,
             schemas: &IndexMap<String, TycoStruct>,
@@ -257,7 +1511,7 @@ impl TycoContext {
                     let coerced = coerce_value(value, field)?;
                     instance.set_attribute(field.name.clone(), coerced);
                 } else if let Some(default) = &field.default_value {
-                    instance.set_attribute(field.name.clone(), default.clone());
+                    instance.set_default_attribute(field.name.clone(), default.clone());
                 }
             }
 
@@ -291,36 +1545,37 @@ impl TycoContext {
     }
 
     fn resolve_references(&mut self) -> Result<(), TycoError> {
-        let struct_snapshot = self.structs.clone();
-
         fn visit(
             value: &mut TycoValue,
             structs: &IndexMap<String, TycoStruct>,
+            path: &str,
+            found: &mut Vec<(String, usize, String)>,
         ) -> Result<(), TycoError> {
             match value {
                 TycoValue::Reference(reference) => {
                     let struct_def = structs
                         .get(&reference.struct_name)
                         .ok_or_else(|| TycoError::UnknownStruct(reference.struct_name.clone()))?;
-                    let pk = struct_def
-                        .find_by_primary_key(&reference.primary_key)
-                        .cloned()
+                    reference.primary_key = struct_def.normalize_key(&reference.primary_key)?;
+                    let position = struct_def
+                        .position_by_primary_key(&reference.primary_key)
                         .ok_or_else(|| {
                             TycoError::Reference(format!(
                                 "Unknown {}({})",
                                 reference.struct_name, reference.primary_key
                             ))
                         })?;
-                    reference.resolved = Some(Box::new(pk));
+                    reference.resolved = Some(position);
+                    found.push((reference.struct_name.clone(), position, path.to_string()));
                 }
                 TycoValue::Array(items) => {
-                    for item in items {
-                        visit(item, structs)?;
+                    for (idx, item) in items.iter_mut().enumerate() {
+                        visit(item, structs, &format!("{path}[{idx}]"), found)?;
                     }
                 }
                 TycoValue::Instance(instance) => {
-                    for value in instance.attributes_mut().values_mut() {
-                        visit(value, structs)?;
+                    for (key, value) in instance.attributes_mut().iter_mut() {
+                        visit(value, structs, &format!("{path}.{key}"), found)?;
                     }
                 }
                 _ => {}
@@ -328,79 +1583,295 @@ impl TycoContext {
             Ok(())
         }
 
-        let global_keys = self.globals.keys().cloned().collect::<Vec<_>>();
-        for key in global_keys {
-            if let Some(value) = self.globals.get_mut(&key) {
-                visit(value, &struct_snapshot)?;
+        self.referrers.clear();
+        let mut found = Vec::new();
+
+        // Lookups only need the schemas and primary indexes, so each value
+        // container is detached while it is visited instead of cloning the
+        // whole context.
+        let mut globals = std::mem::take(&mut self.globals);
+        let mut result = Ok(());
+        for (name, value) in globals.iter_mut() {
+            result = visit(value, &self.structs, name, &mut found);
+            if result.is_err() {
+                break;
             }
+            self.record_referrers(found.drain(..), ReferenceOwner::Global(name.clone()));
         }
+        self.globals = globals;
+        result?;
 
-        for struct_def in self.structs.values_mut() {
-            for instance in struct_def.instances_mut() {
-                for value in instance.attributes_mut().values_mut() {
-                    visit(value, &struct_snapshot)?;
+        for idx in 0..self.structs.len() {
+            let (name, mut instances) = match self.structs.get_index_mut(idx) {
+                Some((name, struct_def)) => (name.clone(), std::mem::take(struct_def.instances_mut())),
+                None => continue,
+            };
+            let mut result = Ok(());
+            for (index, instance) in instances.iter_mut().enumerate() {
+                let prefix = self.structs[idx].instance_path(index, instance);
+                for (key, value) in instance.attributes_mut().iter_mut() {
+                    result = visit(value, &self.structs, &format!("{prefix}.{key}"), &mut found);
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                if result.is_err() {
+                    break;
                 }
+                let owner = ReferenceOwner::Instance {
+                    struct_name: name.clone(),
+                    index,
+                };
+                self.record_referrers(found.drain(..), owner);
             }
+            if let Some((_, struct_def)) = self.structs.get_index_mut(idx) {
+                *struct_def.instances_mut() = instances;
+            }
+            result?;
         }
         Ok(())
     }
 
-    fn render_templates(&mut self) {
-        let mut snapshot = self.clone();
-        let global_keys = self.globals.keys().cloned().collect::<Vec<_>>();
-        for key in global_keys {
-            if let Some(value) = self.globals.get_mut(&key) {
-                value.render_templates(&snapshot, None);
-                snapshot
-                    .globals_mut()
-                    .insert(key.clone(), value.clone());
+    fn record_referrers(
+        &mut self,
+        found: impl Iterator<Item = (String, usize, String)>,
+        owner: ReferenceOwner,
+    ) {
+        for (struct_name, index, path) in found {
+            self.referrers
+                .entry((struct_name, index))
+                .or_default()
+                .push(Referrer {
+                    owner: owner.clone(),
+                    path,
+                });
+        }
+    }
+
+    /// Every value that references the given instance, in document order:
+    /// globals first, then struct instances. Empty when nothing refers to it
+    /// or the instance does not exist.
+    pub fn referrers_of(&self, struct_name: &str, key: impl Into<PrimaryKey>) -> &[Referrer] {
+        self.get_struct(struct_name)
+            .and_then(|struct_def| struct_def.position_by_primary_key(key))
+            .and_then(|index| self.referrers.get(&(struct_name.to_string(), index)))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Instances of primary-keyed structs that no global or instance
+    /// references, in document order. Structs without a primary key cannot
+    /// be referenced and are skipped.
+    pub fn unused_instances(&self) -> Vec<UnusedInstance> {
+        let mut unused = Vec::new();
+        for (struct_name, struct_def) in &self.structs {
+            for (index, instance) in struct_def.instances().iter().enumerate() {
+                if self.referrers.contains_key(&(struct_name.clone(), index)) {
+                    continue;
+                }
+                let Some(key) = struct_def.primary_key_of(instance) else {
+                    continue;
+                };
+                unused.push(UnusedInstance {
+                    struct_name: struct_name.clone(),
+                    index,
+                    path: struct_def.instance_path(index, instance),
+                    key,
+                });
+            }
+        }
+        unused
+    }
+
+    /// Globals that no template placeholder resolved through, in declaration
+    /// order. Only meaningful after `render`.
+    pub fn unused_globals(&self) -> Vec<&str> {
+        self.globals
+            .keys()
+            .filter(|name| !self.used_globals.contains(name.as_str()))
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn render_templates(&mut self, order: Vec<InstanceId>) {
+        // Each value is taken out of the context while it renders, so its
+        // templates read the context in place rather than a copy of it;
+        // values rendered earlier are already back in place.
+        let mut used_globals = HashSet::new();
+        for idx in 0..self.globals.len() {
+            let mut value = std::mem::replace(&mut self.globals[idx], TycoValue::Null);
+            value.render_templates_tracking(self, None, &mut used_globals);
+            self.globals[idx] = value;
+        }
+
+        for (struct_idx, instance_idx) in order {
+            let Some(keys) = self
+                .instance_at(struct_idx, instance_idx)
+                .map(|instance| instance.field_order().to_vec())
+            else {
+                continue;
+            };
+            for key in keys {
+                let Some(slot) = self.field_at_mut(struct_idx, instance_idx, &key) else {
+                    continue;
+                };
+                let mut value = std::mem::replace(slot, TycoValue::Null);
+                value.render_templates_tracking(
+                    self,
+                    self.instance_at(struct_idx, instance_idx),
+                    &mut used_globals,
+                );
+                if let Some(slot) = self.field_at_mut(struct_idx, instance_idx, &key) {
+                    *slot = value;
+                }
             }
         }
+        self.used_globals = used_globals;
+    }
 
-        let struct_names = self.structs.keys().cloned().collect::<Vec<_>>();
-        for name in struct_names {
-            let Some(struct_def) = self.structs.get_mut(&name) else {
+    fn instance_at(&self, struct_idx: usize, instance_idx: usize) -> Option<&TycoInstance> {
+        self.structs
+            .get_index(struct_idx)
+            .and_then(|(_, struct_def)| struct_def.instances().get(instance_idx))
+    }
+
+    fn field_at_mut(
+        &mut self,
+        struct_idx: usize,
+        instance_idx: usize,
+        field: &str,
+    ) -> Option<&mut TycoValue> {
+        self.structs
+            .get_index_mut(struct_idx)
+            .and_then(|(_, struct_def)| struct_def.instances_mut().get_mut(instance_idx))
+            .and_then(|instance| instance.attributes_mut().get_mut(field))
+    }
+
+    /// Orders struct instances so that each one comes after every instance it
+    /// references, which lets templates read fully rendered values through
+    /// any number of references. Fails with the whole chain on a cycle.
+    fn dependency_order(&self) -> Result<Vec<InstanceId>, TycoError> {
+        let mut order = Vec::new();
+        let mut finished = HashMap::new();
+        for (struct_idx, struct_def) in self.structs.values().enumerate() {
+            for instance_idx in 0..struct_def.instances().len() {
+                self.visit_dependencies((struct_idx, instance_idx), &mut finished, &mut order)?;
+            }
+        }
+        Ok(order)
+    }
+
+    /// Depth-first walk from `root` with an explicit stack, so long reference
+    /// chains cannot overflow the call stack. Each frame holds an instance
+    /// being visited and the targets it has left to visit; the frames form
+    /// the chain reported on a cycle.
+    fn visit_dependencies(
+        &self,
+        root: InstanceId,
+        finished: &mut HashMap<InstanceId, bool>,
+        order: &mut Vec<InstanceId>,
+    ) -> Result<(), TycoError> {
+        if finished.contains_key(&root) {
+            return Ok(());
+        }
+        finished.insert(root, false);
+        let mut stack = vec![(root, self.reference_targets(root).into_iter())];
+        while let Some((node, targets)) = stack.last_mut() {
+            let Some(target) = targets.next() else {
+                let node = *node;
+                stack.pop();
+                finished.insert(node, true);
+                order.push(node);
                 continue;
             };
-            for (idx, instance) in struct_def.instances_mut().iter_mut().enumerate() {
-                let keys = instance.field_order().to_vec();
-                let mut instance_snapshot = instance.clone();
-                for key in keys {
-                    if let Some(value) = instance.attributes_mut().get_mut(&key) {
-                        value.render_templates(&snapshot, Some(&instance_snapshot));
-                    }
-                    instance_snapshot = instance.clone();
+            match finished.get(&target) {
+                Some(true) => {}
+                Some(false) => {
+                    let start = stack
+                        .iter()
+                        .position(|(entry, _)| *entry == target)
+                        .unwrap_or(0);
+                    let chain = stack[start..]
+                        .iter()
+                        .map(|(entry, _)| *entry)
+                        .chain(std::iter::once(target))
+                        .map(|entry| self.describe_instance(entry))
+                        .collect();
+                    return Err(TycoError::ReferenceCycle(chain));
                 }
-                if let Some(snapshot_struct) = snapshot.get_struct_mut(&name) {
-                    if let Some(slot) = snapshot_struct.instances_mut().get_mut(idx) {
-                        *slot = instance.clone();
-                    } else {
-                        snapshot_struct.instances_mut().push(instance.clone());
-                    }
+                None => {
+                    finished.insert(target, false);
+                    stack.push((target, self.reference_targets(target).into_iter()));
                 }
             }
         }
+        Ok(())
+    }
+
+    fn reference_targets(&self, node: InstanceId) -> Vec<InstanceId> {
+        let mut targets = Vec::new();
+        if let Some(instance) = self
+            .structs
+            .get_index(node.0)
+            .and_then(|(_, struct_def)| struct_def.instances().get(node.1))
+        {
+            for value in instance.attributes().values() {
+                self.collect_reference_targets(value, &mut targets);
+            }
+        }
+        targets
+    }
+
+    fn collect_reference_targets(&self, value: &TycoValue, targets: &mut Vec<InstanceId>) {
+        match value {
+            TycoValue::Reference(reference) => {
+                if let (Some(struct_idx), Some(instance_idx)) = (
+                    self.structs.get_index_of(&reference.struct_name),
+                    reference.resolved,
+                ) {
+                    targets.push((struct_idx, instance_idx));
+                }
+            }
+            TycoValue::Array(items) => {
+                for item in items {
+                    self.collect_reference_targets(item, targets);
+                }
+            }
+            TycoValue::Instance(instance) => {
+                for value in instance.attributes().values() {
+                    self.collect_reference_targets(value, targets);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn describe_instance(&self, (struct_idx, instance_idx): InstanceId) -> String {
+        let Some((name, struct_def)) = self.structs.get_index(struct_idx) else {
+            return format!("#{struct_idx}[{instance_idx}]");
+        };
+        match struct_def
+            .instances()
+            .get(instance_idx)
+            .and_then(|instance| struct_def.primary_key_of(instance))
+        {
+            Some(key) => format!("{name}({key})"),
+            None => format!("{name}[{instance_idx}]"),
+        }
     }
 
     pub fn to_json(&self) -> JsonValue {
+        self.to_json_with(&JsonOptions::default())
+    }
+
+    pub fn to_json_with(&self, options: &JsonOptions) -> JsonValue {
         let mut map = serde_json::Map::new();
         for (name, value) in self.globals.iter() {
-            map.insert(name.clone(), value.to_json_value());
+            map.insert(name.clone(), value.to_json_value_in_with(self, options));
         }
         for (name, struct_def) in self.structs.iter() {
-            if struct_def.primary_key_field().is_some() {
-                let instances = struct_def
-                    .instances()
-                    .iter()
-                    .map(|instance| {
-                        let mut obj = serde_json::Map::new();
-                        for key in instance.field_order() {
-                            if let Some(value) = instance.get_attribute(key) {
-                                obj.insert(key.clone(), value.to_json_value());
-                            }
-                        }
-                        JsonValue::Object(obj)
-                    })
+            if struct_def.has_primary_key() {
+                let instances = (0..struct_def.instances().len())
+                    .map(|index| struct_instance_json(self, options, name, index))
                     .collect::<Vec<_>>();
                 map.insert(name.clone(), JsonValue::Array(instances));
             }
@@ -408,6 +1879,28 @@ impl TycoContext {
         JsonValue::Object(map)
     }
 
+    /// A JSON Schema (draft 2020-12) for the document `to_json` produces:
+    /// one property per global, an array per struct with a primary key, and
+    /// a definition under `$defs` for every struct. Field defaults are
+    /// included as `default` annotations.
+    pub fn to_json_schema(&self) -> JsonValue {
+        self.to_json_schema_with(&JsonOptions::default())
+    }
+
+    /// The schema for the document `to_json_with(options)` produces.
+    pub fn to_json_schema_with(&self, options: &JsonOptions) -> JsonValue {
+        schema::context_schema(self, options)
+    }
+
+    /// The context as a `.tyco` document: globals, then each struct's schema
+    /// and instances. Templates and defaults are written as declared rather
+    /// than rendered, and included files are written inline. Contexts with
+    /// imported namespaces cannot be written, as their structs belong to
+    /// the imported file.
+    pub fn to_tyco(&self) -> Result<String, TycoError> {
+        writer::write_context(self)
+    }
+
     pub fn to_object(&self) -> JsonValue {
         self.to_json()
     }
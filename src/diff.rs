@@ -0,0 +1,562 @@
+//! Semantic differences between two contexts, for reviewing configuration
+//! changes without reading aligned instance tables line by line.
+
+use std::fmt;
+
+use crate::{
+    builder::{checked_instance, coerce, infer_type, parse_declaration},
+    context::{FieldSchema, PrimaryKey, TycoContext, TycoStruct},
+    error::TycoError,
+    parser::TycoParser,
+    path::{parse_path, split_qualified_name, PathSegment},
+    value::{TycoInstance, TycoValue},
+};
+
+/// One difference found by `diff`. Paths use the syntax of
+/// `TycoContext::get`: `timezone`, `Host.cores` for a schema field,
+/// `Host[prod-02-us]` for an instance and `Host[prod-02-us].os` for one of
+/// its fields.
+#[derive(Clone, Debug)]
+pub enum Change {
+    GlobalAdded {
+        name: String,
+        value: TycoValue,
+    },
+    GlobalRemoved {
+        name: String,
+        value: TycoValue,
+    },
+    /// A global or instance field whose value differs. `old` is null for
+    /// fields added to the schema and set explicitly on the instance.
+    ValueChanged {
+        path: String,
+        old: TycoValue,
+        new: TycoValue,
+    },
+    FieldAdded {
+        struct_name: String,
+        field: FieldSchema,
+    },
+    FieldRemoved {
+        struct_name: String,
+        field: FieldSchema,
+    },
+    /// A schema field whose type, array, nullable or primary key marker
+    /// changed.
+    FieldRetyped {
+        struct_name: String,
+        old: FieldSchema,
+        new: FieldSchema,
+    },
+    /// A struct default that changed, was added or was removed. Instances
+    /// that use the default on both sides are not reported separately.
+    DefaultChanged {
+        struct_name: String,
+        field: String,
+        old: Option<TycoValue>,
+        new: Option<TycoValue>,
+    },
+    InstanceAdded {
+        path: String,
+        instance: TycoInstance,
+    },
+    InstanceRemoved {
+        path: String,
+        instance: TycoInstance,
+    },
+}
+
+impl Change {
+    /// Sets a global, a struct default or an instance field, for patches
+    /// passed to `TycoContext::apply_patch`. The old value is not checked.
+    pub fn set(path: impl Into<String>, value: impl Into<TycoValue>) -> Self {
+        Change::ValueChanged {
+            path: path.into(),
+            old: TycoValue::Null,
+            new: value.into(),
+        }
+    }
+
+    /// Removes the instance at `path`, e.g. `Host[prod-03-eu]`, for patches
+    /// passed to `TycoContext::apply_patch`.
+    pub fn remove_instance(path: impl Into<String>) -> Self {
+        let path = path.into();
+        let struct_name = path.split('[').next().unwrap_or_default().to_string();
+        Change::InstanceRemoved {
+            instance: TycoInstance::new(struct_name),
+            path,
+        }
+    }
+
+    pub fn path(&self) -> String {
+        match self {
+            Change::GlobalAdded { name, .. } | Change::GlobalRemoved { name, .. } => name.clone(),
+            Change::ValueChanged { path, .. }
+            | Change::InstanceAdded { path, .. }
+            | Change::InstanceRemoved { path, .. } => path.clone(),
+            Change::FieldAdded { struct_name, field }
+            | Change::FieldRemoved { struct_name, field } => {
+                format!("{struct_name}.{}", field.name)
+            }
+            Change::FieldRetyped {
+                struct_name, new, ..
+            } => format!("{struct_name}.{}", new.name),
+            Change::DefaultChanged {
+                struct_name, field, ..
+            } => format!("{struct_name}.{field}"),
+        }
+    }
+}
+
+/// One line per change: `+` for additions, `-` for removals and `~` for
+/// modifications, e.g. `~ Host[prod-02-us].os: Fedora -> Debian`.
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path();
+        match self {
+            Change::GlobalAdded { value, .. } => write!(f, "+ {path} = {}", describe(value)),
+            Change::GlobalRemoved { value, .. } => write!(f, "- {path} = {}", describe(value)),
+            Change::ValueChanged { old, new, .. } => {
+                write!(f, "~ {path}: {} -> {}", describe(old), describe(new))
+            }
+            Change::FieldAdded { field, .. } => {
+                write!(f, "+ field {path}: {}", describe_field(field))
+            }
+            Change::FieldRemoved { field, .. } => {
+                write!(f, "- field {path}: {}", describe_field(field))
+            }
+            Change::FieldRetyped { old, new, .. } => write!(
+                f,
+                "~ field {path}: {} -> {}",
+                describe_field(old),
+                describe_field(new)
+            ),
+            Change::DefaultChanged { old, new, .. } => write!(
+                f,
+                "~ default {path}: {} -> {}",
+                describe_default(old),
+                describe_default(new)
+            ),
+            Change::InstanceAdded { instance, .. } => {
+                write!(f, "+ {path} {}", describe_instance(instance))
+            }
+            Change::InstanceRemoved { .. } => write!(f, "- {path}"),
+        }
+    }
+}
+
+/// The changes that turn `old` into `new`: globals first, then each struct's
+/// removed instances, schema fields and remaining instances. Instances are
+/// matched by primary key, and by position for structs without one. Values
+/// are compared after rendering; a changed default is reported once, for the
+/// struct, and only instances that stop or start using it are listed.
+pub fn diff(old: &TycoContext, new: &TycoContext) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    for (name, value) in old.globals() {
+        match new.globals().get(name) {
+            None => changes.push(Change::GlobalRemoved {
+                name: name.clone(),
+                value: value.clone(),
+            }),
+            Some(updated) if !same_value(value, updated) => changes.push(Change::ValueChanged {
+                path: name.clone(),
+                old: value.clone(),
+                new: updated.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (name, value) in new.globals() {
+        if !old.globals().contains_key(name) {
+            changes.push(Change::GlobalAdded {
+                name: name.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+
+    let empty = |struct_def: &TycoStruct| TycoStruct::new(struct_def.name());
+    for (name, struct_def) in old.structs() {
+        if new.get_struct(name).is_none() {
+            diff_struct(struct_def, &empty(struct_def), &mut changes);
+        }
+    }
+    for (name, struct_def) in new.structs() {
+        match old.get_struct(name) {
+            Some(previous) => diff_struct(previous, struct_def, &mut changes),
+            None => diff_struct(&empty(struct_def), struct_def, &mut changes),
+        }
+    }
+    changes
+}
+
+fn diff_struct(old: &TycoStruct, new: &TycoStruct, changes: &mut Vec<Change>) {
+    // Removed instances come first, last-first, so that applying the changes
+    // in order finds them by their old key or position.
+    let matched = match_instances(old, new);
+    for (idx, instance) in old.instances().iter().enumerate().rev() {
+        if !matched.iter().any(|(old_idx, _)| *old_idx == Some(idx)) {
+            changes.push(Change::InstanceRemoved {
+                path: old.instance_path(idx, instance),
+                instance: instance.clone(),
+            });
+        }
+    }
+
+    let struct_name = new.name().to_string();
+    for field in old.fields() {
+        match new.fields().iter().find(|f| f.name == field.name) {
+            None => changes.push(Change::FieldRemoved {
+                struct_name: struct_name.clone(),
+                field: field.clone(),
+            }),
+            Some(updated) if describe_field(field) != describe_field(updated) => {
+                changes.push(Change::FieldRetyped {
+                    struct_name: struct_name.clone(),
+                    old: field.clone(),
+                    new: updated.clone(),
+                })
+            }
+            Some(updated) if !same_default(&field.default_value, &updated.default_value) => {
+                changes.push(Change::DefaultChanged {
+                    struct_name: struct_name.clone(),
+                    field: field.name.clone(),
+                    old: field.default_value.clone(),
+                    new: updated.default_value.clone(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for field in new.fields() {
+        if !old.fields().iter().any(|f| f.name == field.name) {
+            changes.push(Change::FieldAdded {
+                struct_name: struct_name.clone(),
+                field: field.clone(),
+            });
+        }
+    }
+    for (old_idx, new_idx) in matched {
+        let instance = &new.instances()[new_idx];
+        let path = new.instance_path(new_idx, instance);
+        match old_idx {
+            Some(old_idx) => diff_instance(&path, &old.instances()[old_idx], instance, changes),
+            None => changes.push(Change::InstanceAdded {
+                path,
+                instance: instance.clone(),
+            }),
+        }
+    }
+}
+
+/// Pairs every new instance with the old instance it replaces, if any.
+pub(crate) fn match_instances(old: &TycoStruct, new: &TycoStruct) -> Vec<(Option<usize>, usize)> {
+    new.instances()
+        .iter()
+        .enumerate()
+        .map(|(new_idx, instance)| {
+            let old_idx = match new.primary_key_of(instance) {
+                Some(key) if old.has_primary_key() => old
+                    .instances()
+                    .iter()
+                    .position(|existing| old.primary_key_of(existing).as_ref() == Some(&key)),
+                Some(_) => None,
+                None if old.has_primary_key() => None,
+                None => (new_idx < old.instances().len()).then_some(new_idx),
+            };
+            (old_idx, new_idx)
+        })
+        .collect()
+}
+
+fn diff_instance(path: &str, old: &TycoInstance, new: &TycoInstance, changes: &mut Vec<Change>) {
+    // Fields removed from the schema are reported once, as `FieldRemoved`,
+    // and so are fields added with a default that the instance uses and
+    // defaults the instance uses on both sides.
+    for (field, value) in old.attributes() {
+        if old.is_default(field) && new.is_default(field) {
+            continue;
+        }
+        match new.get_attribute(field) {
+            Some(updated) if !same_value(value, updated) => changes.push(Change::ValueChanged {
+                path: format!("{path}.{field}"),
+                old: value.clone(),
+                new: updated.clone(),
+            }),
+            _ => {}
+        }
+    }
+    for (field, value) in new.attributes() {
+        if !old.has_attribute(field) && !new.is_default(field) {
+            changes.push(Change::ValueChanged {
+                path: format!("{path}.{field}"),
+                old: TycoValue::Null,
+                new: value.clone(),
+            });
+        }
+    }
+}
+
+/// Applies one change to `context` without rendering it, checking it
+/// against the schema: values must have their field's type, added instances
+/// must declare every required field and keep primary keys unique, and
+/// retyped fields must still hold every instance's value. Instance paths are
+/// resolved against the instances present when the change is applied, so
+/// changes must be applied in the order `diff` lists them.
+pub(crate) fn apply_change(context: &mut TycoContext, change: &Change) -> Result<(), TycoError> {
+    let missing =
+        |path: &str| TycoError::Schema(format!("Cannot apply change: no such value '{path}'"));
+    match change {
+        Change::GlobalAdded { name, value } => {
+            if !TycoParser::is_valid_field_name(name) {
+                return Err(TycoError::Schema(format!("Invalid global name '{name}'")));
+            }
+            if context.globals().contains_key(name) {
+                return Err(TycoError::Schema(format!(
+                    "Global '{name}' is already defined"
+                )));
+            }
+            context.set_global(name.clone(), value.clone());
+            if let Some(type_name) = infer_type(value) {
+                context.set_global_type(name.clone(), type_name);
+            }
+        }
+        Change::GlobalRemoved { name, .. } => {
+            context
+                .globals_mut()
+                .shift_remove(name)
+                .ok_or_else(|| missing(name))?;
+        }
+        Change::ValueChanged { path, new, .. } => set_value(context, path, new.clone())?,
+        Change::FieldAdded { struct_name, field } => {
+            let qualified = format!("{struct_name}.{}", field.name);
+            if !TycoParser::is_valid_field_name(&field.name) {
+                return Err(TycoError::Schema(format!(
+                    "Invalid field name '{qualified}'"
+                )));
+            }
+            let struct_def = match context.get_struct(struct_name) {
+                Some(_) => context.get_struct_mut(struct_name).expect("struct exists"),
+                None => {
+                    context.add_struct(TycoStruct::new(struct_name.clone()));
+                    context
+                        .get_struct_mut(struct_name)
+                        .expect("struct was just added")
+                }
+            };
+            if struct_def.fields().iter().any(|f| f.name == field.name) {
+                return Err(TycoError::Schema(format!(
+                    "Field '{qualified}' is already declared"
+                )));
+            }
+            struct_def.add_field(checked_field(field, &qualified)?);
+        }
+        Change::FieldRetyped {
+            struct_name, new, ..
+        } => {
+            let qualified = format!("{struct_name}.{}", new.name);
+            let field = checked_field(new, &qualified)?;
+            let struct_def = context
+                .get_struct_mut(struct_name)
+                .filter(|struct_def| struct_def.fields().iter().any(|f| f.name == new.name))
+                .ok_or_else(|| missing(&qualified))?;
+            for instance in struct_def.instances_mut() {
+                if instance.is_default(&field.name) {
+                    continue;
+                }
+                if let Some(value) = instance.remove_attribute(&field.name) {
+                    instance.set_attribute(field.name.clone(), coerce(value, &field, &qualified)?);
+                }
+            }
+            struct_def.add_field(field);
+        }
+        Change::FieldRemoved { struct_name, field } => {
+            context
+                .get_struct_mut(struct_name)
+                .and_then(|struct_def| struct_def.remove_field(&field.name))
+                .ok_or_else(|| missing(&change.path()))?;
+        }
+        Change::DefaultChanged {
+            struct_name,
+            field,
+            new,
+            ..
+        } => {
+            let qualified = change.path();
+            let struct_def = context
+                .get_struct_mut(struct_name)
+                .ok_or_else(|| missing(&qualified))?;
+            let schema = struct_def
+                .fields()
+                .iter()
+                .find(|schema| schema.name == *field)
+                .ok_or_else(|| missing(&qualified))?;
+            let value = new
+                .clone()
+                .map(|value| coerce(value, schema, &qualified))
+                .transpose()?;
+            struct_def.set_default(field, value)?;
+        }
+        Change::InstanceAdded { instance, .. } => {
+            let struct_def = context
+                .get_struct_mut(instance.struct_name())
+                .ok_or_else(|| TycoError::UnknownStruct(instance.struct_name().to_string()))?;
+            let instance = checked_instance(struct_def, instance.clone())?;
+            struct_def.add_instance(instance);
+            struct_def.build_primary_index()?;
+        }
+        Change::InstanceRemoved { path, .. } => {
+            let segments = parse_path(path)?;
+            let Some((struct_name, index, [])) = locate_instance(context, &segments) else {
+                return Err(missing(path));
+            };
+            let struct_def = context
+                .get_struct_mut(&struct_name)
+                .ok_or_else(|| missing(path))?;
+            struct_def.instances_mut().remove(index);
+            struct_def.build_primary_index()?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets a global, a struct default (`Host.cores`) or an instance field,
+/// converting the value to the declared type.
+fn set_value(context: &mut TycoContext, path: &str, value: TycoValue) -> Result<(), TycoError> {
+    let missing = || TycoError::Schema(format!("Cannot apply change: no such value '{path}'"));
+    if context.globals().contains_key(path) {
+        let value = match context.global_type(path) {
+            Some(type_name) => coerce(value, &parse_declaration(path, type_name)?, path)?,
+            None => value,
+        };
+        context.set_global(path, value);
+        return Ok(());
+    }
+    let segments = parse_path(path)?;
+    let (struct_name, index, field) = match locate_instance(context, &segments) {
+        Some((struct_name, index, [PathSegment::Name(field)])) => (struct_name, Some(index), field),
+        Some(_) => return Err(missing()),
+        None => match split_qualified_name(&segments, |name| context.get_struct(name).is_some()) {
+            Some((struct_name, [PathSegment::Name(field)])) => (struct_name, None, field),
+            _ => return Err(missing()),
+        },
+    };
+    let struct_def = context.get_struct_mut(&struct_name).ok_or_else(missing)?;
+    let schema = struct_def
+        .fields()
+        .iter()
+        .find(|schema| schema.name == *field)
+        .ok_or_else(missing)?;
+    let qualified = format!("{struct_name}.{field}");
+    if schema.is_primary_key {
+        return Err(TycoError::Schema(format!(
+            "Cannot change '{qualified}': primary key fields cannot be changed"
+        )));
+    }
+    let value = coerce(value, schema, &qualified)?;
+    match index {
+        Some(index) => struct_def.instances_mut()[index].set_attribute(field.clone(), value),
+        None => struct_def.set_default(field, Some(value))?,
+    }
+    Ok(())
+}
+
+/// A copy of `field` with its default converted to the field's type.
+fn checked_field(field: &FieldSchema, qualified: &str) -> Result<FieldSchema, TycoError> {
+    let mut field = field.clone();
+    if let Some(default) = field.default_value.take() {
+        field.default_value = Some(coerce(default, &field, qualified)?);
+    }
+    Ok(field)
+}
+
+/// The struct and position of the instance a path starts with, and the rest
+/// of the path.
+fn locate_instance<'p>(
+    context: &TycoContext,
+    segments: &'p [PathSegment],
+) -> Option<(String, usize, &'p [PathSegment])> {
+    let (name, rest) = split_qualified_name(segments, |name| context.get_struct(name).is_some())?;
+    let [PathSegment::Index(key), rest @ ..] = rest else {
+        return None;
+    };
+    let index = context.get_struct(&name)?.index_for_path_key(key)?;
+    Some((name, index, rest))
+}
+
+/// Structural equality of rendered values. Scalars compare like primary key
+/// parts, so `int` 16 equals `uint` 16 and `1.50` equals `1.5` for
+/// decimals; references compare by target.
+pub(crate) fn same_value(a: &TycoValue, b: &TycoValue) -> bool {
+    match (a, b) {
+        (TycoValue::Array(a), TycoValue::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_value(a, b))
+        }
+        (TycoValue::Instance(a), TycoValue::Instance(b)) => same_instance(a, b),
+        (TycoValue::Reference(a), TycoValue::Reference(b)) => {
+            a.struct_name == b.struct_name && a.primary_key == b.primary_key
+        }
+        (TycoValue::Array(_) | TycoValue::Instance(_) | TycoValue::Reference(_), _)
+        | (_, TycoValue::Array(_) | TycoValue::Instance(_) | TycoValue::Reference(_)) => false,
+        (a, b) => PrimaryKey::from(a.clone()) == PrimaryKey::from(b.clone()),
+    }
+}
+
+pub(crate) fn same_default(a: &Option<TycoValue>, b: &Option<TycoValue>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => same_value(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+pub(crate) fn same_instance(a: &TycoInstance, b: &TycoInstance) -> bool {
+    a.struct_name() == b.struct_name()
+        && a.attributes().len() == b.attributes().len()
+        && a.attributes().iter().all(|(field, value)| {
+            b.get_attribute(field)
+                .is_some_and(|other| same_value(value, other))
+        })
+}
+
+/// Compact text for a value: scalars as written, `[a, b]` for arrays and
+/// `Host(prod-01)` for references.
+fn describe(value: &TycoValue) -> String {
+    match value {
+        TycoValue::Array(items) => {
+            let items = items.iter().map(describe).collect::<Vec<_>>();
+            format!("[{}]", items.join(", "))
+        }
+        TycoValue::Instance(instance) => describe_instance(instance),
+        TycoValue::Reference(reference) => {
+            format!("{}({})", reference.struct_name, reference.primary_key)
+        }
+        other => other.to_template_text(),
+    }
+}
+
+fn describe_default(value: &Option<TycoValue>) -> String {
+    value.as_ref().map_or_else(|| "none".to_string(), describe)
+}
+
+fn describe_instance(instance: &TycoInstance) -> String {
+    let fields = instance
+        .attributes()
+        .iter()
+        .map(|(field, value)| format!("{field}: {}", describe(value)))
+        .collect::<Vec<_>>();
+    format!("{}({})", instance.struct_name(), fields.join(", "))
+}
+
+/// A field declaration as written in a file: `*str`, `?int`, `Host[]`.
+fn describe_field(field: &FieldSchema) -> String {
+    let marker = if field.is_primary_key {
+        "*"
+    } else if field.is_nullable {
+        "?"
+    } else {
+        ""
+    };
+    let array = if field.is_array { "[]" } else { "" };
+    format!("{marker}{}{array}", field.type_name)
+}
@@ -41,6 +41,16 @@ impl SourceSpan {
     }
 }
 
+/// A problem reported without failing the parse, such as a redefinition
+/// under `RedefinitionPolicy::Warn`.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<SourceSpan>,
+    /// The earlier definition, for redefinitions.
+    pub previous: Option<SourceSpan>,
+}
+
 /// Shared error type for the Tyco parser.
 #[derive(Debug)]
 pub enum TycoError {
@@ -48,6 +58,23 @@ pub enum TycoError {
     Parse { message: String, span: Option<SourceSpan> },
     UnknownStruct(String),
     Reference(String),
+    ReferenceCycle(Vec<String>),
+    IncludeCycle(Vec<PathBuf>),
+    NumericOverflow {
+        literal: String,
+        type_name: String,
+        span: Option<SourceSpan>,
+    },
+    /// A definition or value that does not fit the schema, reported by
+    /// `ContextBuilder`.
+    Schema(String),
+    /// A typed accessor was used on a value of another type; `field` is set
+    /// by `TycoInstance::get_as`.
+    TypeMismatch {
+        field: Option<String>,
+        expected: String,
+        found: String,
+    },
 }
 
 impl TycoError {
@@ -65,12 +92,35 @@ impl TycoError {
         }
     }
 
+    pub fn overflow(literal: impl Into<String>, type_name: impl Into<String>) -> Self {
+        TycoError::NumericOverflow {
+            literal: literal.into(),
+            type_name: type_name.into(),
+            span: None,
+        }
+    }
+
+    pub fn type_mismatch(expected: impl Into<String>, found: impl Into<String>) -> Self {
+        TycoError::TypeMismatch {
+            field: None,
+            expected: expected.into(),
+            found: found.into(),
+        }
+    }
+
     pub fn with_span(self, span: SourceSpan) -> Self {
         match self {
             TycoError::Parse { message, .. } => TycoError::Parse {
                 message,
                 span: Some(span),
             },
+            TycoError::NumericOverflow {
+                literal, type_name, ..
+            } => TycoError::NumericOverflow {
+                literal,
+                type_name,
+                span: Some(span),
+            },
             other => other,
         }
     }
@@ -89,6 +139,39 @@ impl fmt::Display for TycoError {
             }
             TycoError::UnknownStruct(name) => write!(f, "Unknown struct '{name}'"),
             TycoError::Reference(message) => write!(f, "Reference error: {message}"),
+            TycoError::Schema(message) => write!(f, "Schema error: {message}"),
+            TycoError::ReferenceCycle(chain) => {
+                write!(f, "Reference cycle: {}", chain.join(" -> "))
+            }
+            TycoError::IncludeCycle(chain) => {
+                let files = chain
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "Include cycle: {}", files.join(" -> "))
+            }
+            TycoError::NumericOverflow {
+                literal,
+                type_name,
+                span,
+            } => {
+                write!(f, "Numeric overflow: '{literal}' does not fit in type '{type_name}'")?;
+                if let Some(span) = span {
+                    write!(f, "\n{}", span.display())?;
+                }
+                Ok(())
+            }
+            TycoError::TypeMismatch {
+                field,
+                expected,
+                found,
+            } => {
+                write!(f, "Type mismatch")?;
+                if let Some(field) = field {
+                    write!(f, " for field '{field}'")?;
+                }
+                write!(f, ": expected {expected}, found {found}")
+            }
         }
     }
 }
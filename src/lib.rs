@@ -3,13 +3,31 @@
 //! This crate mirrors the behaviour of the reference Python parser and is kept
 //! in sync with the shared test suite that lives in `../tyco-test-suite`.
 
+mod builder;
 mod context;
+mod diff;
 mod error;
+mod loader;
+mod merge;
+mod numeric;
 mod parser;
+mod path;
+mod schema;
 mod utils;
 mod value;
+mod writer;
 
-pub use context::{FieldSchema, TycoContext, TycoStruct};
-pub use error::TycoError;
-pub use parser::{load, loads, TycoParser};
-pub use value::{TycoInstance, TycoString, TycoValue};
+pub use builder::{ContextBuilder, InstanceBuilder, StructBuilder};
+pub use context::{
+    FieldSchema, OriginKind, PrimaryKey, ReferenceOwner, Referrer, TycoContext, TycoStruct,
+    UnusedInstance, ValueOrigin,
+};
+pub use diff::{diff, Change};
+pub use error::{Diagnostic, SourceSpan, TycoError};
+pub use loader::{FileLoader, FsLoader, MemoryLoader, SourceFile};
+pub use merge::{merge, merge_sources, Conflict, MergeOutcome, MergedSource};
+pub use path::PathMatch;
+pub use parser::{load, loads, RedefinitionPolicy, TycoParser};
+pub use value::{
+    BytesEncoding, JsonOptions, TycoInstance, TycoReference, TycoString, TycoValue,
+};
@@ -0,0 +1,162 @@
+//! Pluggable access to `.tyco` sources so includes can be resolved from disk,
+//! from memory, or from anywhere else a caller keeps configuration fragments.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::error::{SourceSpan, TycoError};
+
+/// Reads the files a parse needs, including every `#include`d fragment.
+pub trait FileLoader {
+    /// Returns the canonical form of `path`, which identifies the file when
+    /// detecting repeated includes. Fails when the file does not exist.
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, TycoError>;
+
+    /// Reads the whole file at a path returned by `canonicalize`.
+    fn read_to_string(&self, path: &Path) -> Result<String, TycoError>;
+
+    /// Whether `path` names a directory, used by directory and glob includes.
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// The files directly inside the directory `path`, in any order.
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, TycoError>;
+}
+
+/// A file read while parsing, as returned by `TycoContext::source_files`.
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    /// Canonical path, as returned by the parser's `FileLoader`.
+    pub path: PathBuf,
+    /// Every `#include` or `#import` line that named this file, in reading
+    /// order, including `#include_once` lines that skipped it. Empty for the
+    /// file passed to `TycoParser::parse_file`.
+    pub included_from: Vec<SourceSpan>,
+}
+
+/// Adds `files` to `known`, keeping the first entry for each path and
+/// collecting every include site.
+pub(crate) fn merge_source_files(known: &mut Vec<SourceFile>, files: Vec<SourceFile>) {
+    for file in files {
+        match known.iter_mut().find(|entry| entry.path == file.path) {
+            Some(entry) => entry.included_from.extend(file.included_from),
+            None => known.push(file),
+        }
+    }
+}
+
+/// Loads files from the local filesystem. Used by `TycoParser::new`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FsLoader;
+
+impl FileLoader for FsLoader {
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, TycoError> {
+        Ok(fs::canonicalize(path)?)
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String, TycoError> {
+        Ok(fs::read_to_string(path)?)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, TycoError> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Serves files from memory, which keeps include behaviour testable without
+/// touching disk. Paths are normalized lexically, so `conf/../base.tyco`
+/// and `base.tyco` name the same file.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLoader {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: impl AsRef<Path>, content: impl Into<String>) {
+        self.files
+            .insert(normalize_path(path.as_ref()), content.into());
+    }
+
+    pub fn with_file(mut self, path: impl AsRef<Path>, content: impl Into<String>) -> Self {
+        self.insert(path, content);
+        self
+    }
+}
+
+impl FileLoader for MemoryLoader {
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf, TycoError> {
+        let normalized = normalize_path(path);
+        if self.files.contains_key(&normalized) {
+            Ok(normalized)
+        } else {
+            Err(not_found(path))
+        }
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String, TycoError> {
+        self.files
+            .get(&normalize_path(path))
+            .cloned()
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        let dir = normalize_path(path);
+        self.files
+            .keys()
+            .any(|file| file != &dir && file.starts_with(&dir))
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<PathBuf>, TycoError> {
+        let dir = normalize_path(path);
+        Ok(self
+            .files
+            .keys()
+            .filter(|file| file.parent() == Some(dir.as_path()))
+            .cloned()
+            .collect())
+    }
+}
+
+fn not_found(path: &Path) -> TycoError {
+    TycoError::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no such file: {}", path.display()),
+    ))
+}
+
+/// Resolves `.` and `..` components without consulting the filesystem.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
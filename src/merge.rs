@@ -0,0 +1,234 @@
+//! Three-way merges of contexts edited concurrently, built on `diff`.
+
+use std::fmt;
+
+use crate::{
+    context::TycoContext,
+    diff::{apply_change, diff, same_default, same_instance, same_value, Change},
+    error::TycoError,
+    parser::loads,
+    path::{parse_path, PathSegment},
+    writer::rewrite_source,
+};
+
+/// The result of `merge`: the merged context, rendered, and the changes
+/// that could not be combined.
+#[derive(Clone, Debug)]
+pub struct MergeOutcome {
+    pub context: TycoContext,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl MergeOutcome {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// The result of `merge_sources`: the merged document and the outcome it
+/// was written from.
+#[derive(Clone, Debug)]
+pub struct MergedSource {
+    pub text: String,
+    pub outcome: MergeOutcome,
+}
+
+/// Changes made on both sides to the same value, instance or schema field.
+/// The merged context keeps `ours`.
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub path: String,
+    pub ours: Change,
+    pub theirs: Change,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "conflict at {}:\n  ours:   {}\n  theirs: {}",
+            self.path, self.ours, self.theirs
+        )
+    }
+}
+
+/// Merges the changes `ours` and `theirs` each made to `base`. Instances are
+/// matched by primary key and compared field by field, so edits to different
+/// instances or different fields of one instance combine cleanly. Two sides
+/// making the same change is not a conflict; a removal on one side and an
+/// edit on the other is, and so is removing a struct on one side while the
+/// other adds instances or fields to it. Instances added to structs without
+/// a primary key are appended from both sides.
+pub fn merge(
+    base: &TycoContext,
+    ours: &TycoContext,
+    theirs: &TycoContext,
+) -> Result<MergeOutcome, TycoError> {
+    let our_changes = diff(base, ours);
+    let their_changes = diff(base, theirs);
+    let shared = |change: &Change| their_changes.iter().any(|other| same_change(change, other));
+
+    let mut context = ours.clone();
+    let mut conflicts = Vec::new();
+    for change in &their_changes {
+        if our_changes.iter().any(|other| same_change(other, change)) {
+            continue;
+        }
+        let appended = match change {
+            Change::InstanceAdded { instance, .. } => context
+                .get_struct(instance.struct_name())
+                .is_some_and(|struct_def| !struct_def.has_primary_key()),
+            _ => false,
+        };
+        let conflict = our_changes.iter().find(|other| {
+            (!appended && overlaps(other, change) && !shared(other))
+                || drops_struct(base, ours, other, change)
+                || drops_struct(base, theirs, change, other)
+        });
+        match conflict {
+            Some(other) => conflicts.push(Conflict {
+                path: change.path(),
+                ours: other.clone(),
+                theirs: change.clone(),
+            }),
+            None => apply_change(&mut context, change)?,
+        }
+    }
+    context.render()?;
+    Ok(MergeOutcome { context, conflicts })
+}
+
+/// `merge` for the text of three standalone `.tyco` files. The merged
+/// document is `ours` with only the lines of the globals, fields and
+/// instances the merge changes rewritten, so its comments and layout are
+/// kept. Files using `#include` or `#import` are rejected, as is any merge
+/// whose rewritten text would not read back as the merged context.
+pub fn merge_sources(base: &str, ours: &str, theirs: &str) -> Result<MergedSource, TycoError> {
+    for (side, source) in [("base", base), ("ours", ours), ("theirs", theirs)] {
+        let directive = source.lines().position(|line| {
+            let line = line.trim();
+            line.starts_with("#include") || line.starts_with("#import")
+        });
+        if let Some(idx) = directive {
+            return Err(TycoError::parse(format!(
+                "Cannot merge {side}: line {} includes another file, which cannot be merged in place",
+                idx + 1
+            )));
+        }
+    }
+    let our_context = loads(ours)?;
+    let outcome = merge(&loads(base)?, &our_context, &loads(theirs)?)?;
+    let text = rewrite_source(ours, &our_context, &outcome.context)?;
+    if !diff(&outcome.context, &loads(&text)?).is_empty() {
+        return Err(TycoError::Schema(
+            "Cannot write the merge into ours without rewriting the whole file".to_string(),
+        ));
+    }
+    Ok(MergedSource { text, outcome })
+}
+
+fn same_change(a: &Change, b: &Change) -> bool {
+    if a.path() != b.path() {
+        return false;
+    }
+    match (a, b) {
+        (Change::GlobalAdded { value: a, .. }, Change::GlobalAdded { value: b, .. })
+        | (Change::ValueChanged { new: a, .. }, Change::ValueChanged { new: b, .. }) => {
+            same_value(a, b)
+        }
+        (Change::FieldAdded { field: a, .. }, Change::FieldAdded { field: b, .. })
+        | (Change::FieldRetyped { new: a, .. }, Change::FieldRetyped { new: b, .. }) => {
+            a.type_name == b.type_name
+                && a.is_array == b.is_array
+                && a.is_nullable == b.is_nullable
+                && a.is_primary_key == b.is_primary_key
+                && same_default(&a.default_value, &b.default_value)
+        }
+        (Change::DefaultChanged { new: a, .. }, Change::DefaultChanged { new: b, .. }) => {
+            same_default(a, b)
+        }
+        (Change::InstanceAdded { instance: a, .. }, Change::InstanceAdded { instance: b, .. }) => {
+            same_instance(a, b)
+        }
+        (Change::GlobalRemoved { .. }, Change::GlobalRemoved { .. })
+        | (Change::FieldRemoved { .. }, Change::FieldRemoved { .. })
+        | (Change::InstanceRemoved { .. }, Change::InstanceRemoved { .. }) => true,
+        _ => false,
+    }
+}
+
+/// Whether two changes touch the same value: one path contains the other
+/// (`Host[web-01]` and `Host[web-01].cores`), or a schema field is removed
+/// or retyped while an instance's value for it changes.
+fn overlaps(a: &Change, b: &Change) -> bool {
+    let (a_path, b_path) = (a.path(), b.path());
+    contains(&a_path, &b_path)
+        || contains(&b_path, &a_path)
+        || schema_field(a).is_some_and(|field| instance_field(b) == Some(field))
+        || schema_field(b).is_some_and(|field| instance_field(a) == Some(field))
+}
+
+/// Whether `removal` is part of `side` deleting a struct of `base` that
+/// `addition` adds an instance or field to.
+fn drops_struct(
+    base: &TycoContext,
+    side: &TycoContext,
+    removal: &Change,
+    addition: &Change,
+) -> bool {
+    removed_from(removal).is_some_and(|name| {
+        added_into(addition) == Some(name)
+            && base.get_struct(name).is_some()
+            && side.get_struct(name).is_none()
+    })
+}
+
+fn removed_from(change: &Change) -> Option<&str> {
+    match change {
+        Change::InstanceRemoved { instance, .. } => Some(instance.struct_name()),
+        Change::FieldRemoved { struct_name, .. } => Some(struct_name),
+        _ => None,
+    }
+}
+
+fn added_into(change: &Change) -> Option<&str> {
+    match change {
+        Change::InstanceAdded { instance, .. } => Some(instance.struct_name()),
+        Change::FieldAdded { struct_name, .. } => Some(struct_name),
+        _ => None,
+    }
+}
+
+fn contains(outer: &str, inner: &str) -> bool {
+    inner
+        .strip_prefix(outer)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+}
+
+/// `Host.cores` for a removed or retyped field. Added fields do not
+/// overlap instance values, which only the side adding the field can set.
+fn schema_field(change: &Change) -> Option<String> {
+    match change {
+        Change::FieldRemoved { .. } | Change::FieldRetyped { .. } => Some(change.path()),
+        _ => None,
+    }
+}
+
+/// `Host.cores` for a change to `Host[web-01].cores`.
+fn instance_field(change: &Change) -> Option<String> {
+    let Change::ValueChanged { path, .. } = change else {
+        return None;
+    };
+    let segments = parse_path(path).ok()?;
+    let [names @ .., PathSegment::Index(_), PathSegment::Name(field)] = segments.as_slice() else {
+        return None;
+    };
+    let names = names
+        .iter()
+        .map(|segment| match segment {
+            PathSegment::Name(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("{}.{field}", names.join(".")))
+}
@@ -0,0 +1,212 @@
+//! Numeric literal lexer shared by every numeric builtin type.
+//!
+//! The grammar follows the Python reference implementation: `_` may separate
+//! digits, integers accept `0x`/`0o`/`0b` prefixes, and floats accept
+//! exponents plus the signed specials `inf` and `nan`.
+
+use crate::error::TycoError;
+
+#[derive(Debug)]
+enum NumberToken {
+    Integer {
+        negative: bool,
+        radix: u32,
+        digits: String,
+    },
+    Float(String),
+    Infinity {
+        negative: bool,
+    },
+    NaN,
+}
+
+fn lex(token: &str) -> Result<NumberToken, TycoError> {
+    let invalid = || TycoError::parse(format!("Invalid numeric literal '{token}'"));
+    let (negative, body) = match token.as_bytes().first() {
+        Some(b'-') => (true, &token[1..]),
+        Some(b'+') => (false, &token[1..]),
+        _ => (false, token),
+    };
+
+    match body {
+        "inf" => return Ok(NumberToken::Infinity { negative }),
+        "nan" => return Ok(NumberToken::NaN),
+        _ => {}
+    }
+
+    for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+        let upper = prefix.to_ascii_uppercase();
+        if let Some(digits) = body
+            .strip_prefix(prefix)
+            .or_else(|| body.strip_prefix(upper.as_str()))
+        {
+            let digits = strip_separators(digits, radix).ok_or_else(invalid)?;
+            return Ok(NumberToken::Integer {
+                negative,
+                radix,
+                digits,
+            });
+        }
+    }
+
+    let (mantissa, exponent) = match body.find(['e', 'E']) {
+        Some(idx) => (&body[..idx], Some(&body[idx + 1..])),
+        None => (body, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
+
+    let whole = strip_separators(whole, 10).ok_or_else(invalid)?;
+    if fraction.is_none() && exponent.is_none() {
+        return Ok(NumberToken::Integer {
+            negative,
+            radix: 10,
+            digits: whole,
+        });
+    }
+
+    let mut text = String::from(if negative { "-" } else { "" });
+    text.push_str(&whole);
+    if let Some(fraction) = fraction {
+        text.push('.');
+        text.push_str(&strip_separators(fraction, 10).ok_or_else(invalid)?);
+    }
+    if let Some(exponent) = exponent {
+        let (sign, digits) = match exponent.as_bytes().first() {
+            Some(b'-') => ("-", &exponent[1..]),
+            Some(b'+') => ("", &exponent[1..]),
+            _ => ("", exponent),
+        };
+        text.push('e');
+        text.push_str(sign);
+        text.push_str(&strip_separators(digits, 10).ok_or_else(invalid)?);
+    }
+    Ok(NumberToken::Float(text))
+}
+
+/// Removes `_` separators, which are only allowed between two digits.
+fn strip_separators(digits: &str, radix: u32) -> Option<String> {
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+    {
+        return None;
+    }
+    let mut cleaned = String::with_capacity(digits.len());
+    for ch in digits.chars() {
+        match ch {
+            '_' => {}
+            c if c.is_digit(radix) => cleaned.push(c),
+            _ => return None,
+        }
+    }
+    Some(cleaned)
+}
+
+fn integer_magnitude(token: &str, type_name: &str) -> Result<(bool, u64), TycoError> {
+    match lex(token)? {
+        NumberToken::Integer {
+            negative,
+            radix,
+            digits,
+        } => u64::from_str_radix(&digits, radix)
+            .map(|magnitude| (negative, magnitude))
+            .map_err(|_| TycoError::overflow(token, type_name)),
+        _ => Err(TycoError::parse(format!(
+            "Failed to parse integer '{token}'"
+        ))),
+    }
+}
+
+pub fn parse_integer(token: &str) -> Result<i64, TycoError> {
+    let token = token.trim();
+    let (negative, magnitude) = integer_magnitude(token, "int")?;
+    let value = if negative {
+        -i128::from(magnitude)
+    } else {
+        i128::from(magnitude)
+    };
+    i64::try_from(value).map_err(|_| TycoError::overflow(token, "int"))
+}
+
+pub fn parse_unsigned(token: &str) -> Result<u64, TycoError> {
+    let token = token.trim();
+    match integer_magnitude(token, "uint")? {
+        (true, magnitude) if magnitude != 0 => Err(TycoError::parse(format!(
+            "Unsigned integer cannot be negative: '{token}'"
+        ))),
+        (_, magnitude) => Ok(magnitude),
+    }
+}
+
+pub fn parse_float(token: &str) -> Result<f64, TycoError> {
+    let token = token.trim();
+    let invalid = || TycoError::parse(format!("Invalid float literal '{token}'"));
+    match lex(token).map_err(|_| invalid())? {
+        NumberToken::Integer {
+            negative,
+            radix: 10,
+            digits,
+        } => {
+            let value = digits.parse::<f64>().map_err(|_| invalid())?;
+            Ok(if negative { -value } else { value })
+        }
+        NumberToken::Integer { .. } => Err(invalid()),
+        NumberToken::Float(text) => text.parse::<f64>().map_err(|_| invalid()),
+        NumberToken::Infinity { negative: false } => Ok(f64::INFINITY),
+        NumberToken::Infinity { negative: true } => Ok(f64::NEG_INFINITY),
+        NumberToken::NaN => Ok(f64::NAN),
+    }
+}
+
+pub fn parse_decimal(token: &str) -> Result<String, TycoError> {
+    let token = token.trim();
+    let invalid = || TycoError::parse(format!("Invalid decimal literal '{token}'"));
+    match lex(token).map_err(|_| invalid())? {
+        NumberToken::Integer {
+            negative,
+            radix: 10,
+            digits,
+        } => Ok(if negative {
+            format!("-{digits}")
+        } else {
+            digits
+        }),
+        NumberToken::Float(text) => Ok(text),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(feature = "bigint")]
+pub fn parse_big_integer(token: &str) -> Result<num_bigint::BigInt, TycoError> {
+    let token = token.trim();
+    match lex(token)? {
+        NumberToken::Integer {
+            negative,
+            radix,
+            digits,
+        } => {
+            let value = num_bigint::BigInt::parse_bytes(digits.as_bytes(), radix)
+                .ok_or_else(|| TycoError::parse(format!("Failed to parse integer '{token}'")))?;
+            Ok(if negative { -value } else { value })
+        }
+        _ => Err(TycoError::parse(format!(
+            "Failed to parse integer '{token}'"
+        ))),
+    }
+}
+
+/// Text used for floats in templates and JSON; non-finite values are spelled
+/// the same way they are written in Tyco source.
+pub fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
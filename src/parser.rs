@@ -1,35 +1,41 @@
 use std::{
-    collections::HashSet,
-    fs,
+    collections::HashMap,
     path::{Path, PathBuf},
 };
 
+use indexmap::IndexMap;
 use once_cell::sync::Lazy;
 use regex::Regex;
 
+#[cfg(feature = "bigint")]
+use crate::numeric::parse_big_integer;
 use crate::{
-    context::{FieldSchema, TycoContext, TycoStruct},
-    error::{SourceSpan, TycoError},
+    context::{FieldSchema, OriginKind, PrimaryKey, TycoContext, TycoStruct, ValueOrigin},
+    error::{Diagnostic, SourceSpan, TycoError},
+    loader::{merge_source_files, FileLoader, FsLoader, SourceFile},
+    numeric::{parse_decimal, parse_float, parse_integer, parse_unsigned},
     utils::{
-        has_unclosed_delimiter, normalize_datetime, normalize_time, parse_integer, split_top_level,
-        strip_inline_comment, strip_leading_newline, unescape_basic_string,
+        has_unclosed_delimiter, normalize_datetime, normalize_time, parse_bytes_literal,
+        split_top_level, strip_inline_comment, strip_leading_newline, unescape_basic_string,
+        wildcard_match,
     },
     value::{TycoInstance, TycoReference, TycoString, TycoValue},
 };
 
-static STRUCT_DEF_RE: Lazy<Regex> =
+pub(crate) static STRUCT_DEF_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^([A-Z][A-Za-z0-9_]*)\s*:$").unwrap());
-static FIELD_RE: Lazy<Regex> = Lazy::new(|| {
+pub(crate) static FIELD_RE: Lazy<Regex> = Lazy::new(|| {
     Regex::new(
-        r"^\s*([*?])?([A-Za-z][A-Za-z0-9_]*)(\[\])?\s+([a-z_][A-Za-z0-9_]*(?:\.[A-Za-z0-9_]+)*)\s*:(?:\s+(.*))?$",
+        r"^\s*([*?])?([A-Za-z][A-Za-z0-9_]*(?:\.[A-Za-z][A-Za-z0-9_]*)*)(\[\])?\s+([a-z_][A-Za-z0-9_]*(?:\.[A-Za-z0-9_]+)*)\s*:(?:\s+(.*))?$",
     )
     .unwrap()
 });
-static DEFAULT_UPDATE_RE: Lazy<Regex> = Lazy::new(|| {
+pub(crate) static DEFAULT_UPDATE_RE: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"^\s+([a-z_][A-Za-z0-9_]*(?:\.[A-Za-z0-9_]+)*)\s*:(?:\s+(.*))?$").unwrap()
 });
-static STRUCT_CALL_RE: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^([A-Za-z][A-Za-z0-9_]*)\((.*)\)$").unwrap());
+static STRUCT_CALL_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^([A-Za-z][A-Za-z0-9_]*(?:\.[A-Za-z][A-Za-z0-9_]*)*)\((.*)\)$").unwrap()
+});
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 enum ParseState {
@@ -73,18 +79,120 @@ impl SourceLine {
     }
 }
 
+/// What the parser does when a global or struct field is declared again with
+/// a different type or value, typically by another included file. Also used
+/// by `TycoContext::try_set_global` and `TycoContext::try_add_struct`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RedefinitionPolicy {
+    /// Fail with a parse error pointing at the second definition.
+    Error,
+    /// Keep the later definition and record a `Diagnostic` on the context.
+    #[default]
+    Warn,
+    /// Keep the later definition silently.
+    Override,
+}
+
+/// An `#import "file.tyco" as alias` directive.
+struct Import {
+    alias: String,
+    path: PathBuf,
+    line: SourceLine,
+}
+
 pub struct TycoParser {
-    included: HashSet<PathBuf>,
+    /// Every file read by the current parse in reading order, with the
+    /// `#include` lines that named it (none for the file being parsed).
+    included: IndexMap<PathBuf, Vec<SourceSpan>>,
+    /// Directories listed by glob and directory includes.
+    scanned_dirs: Vec<PathBuf>,
+    include_stack: Vec<PathBuf>,
+    /// Warnings found while reading includes, moved onto the context once
+    /// it is parsed.
+    include_diagnostics: Vec<Diagnostic>,
+    imports: Vec<Import>,
+    /// Files whose parse is in progress, outermost first, used to report
+    /// import cycles.
+    import_stack: Vec<PathBuf>,
+    loader: Box<dyn FileLoader>,
+    include_paths: Vec<PathBuf>,
+    redefinition_policy: RedefinitionPolicy,
 }
 
 impl TycoParser {
     pub fn new() -> Self {
+        Self::with_loader(FsLoader)
+    }
+
+    /// Creates a parser that reads the main file and every include through
+    /// `loader` instead of the local filesystem.
+    pub fn with_loader(loader: impl FileLoader + 'static) -> Self {
         Self {
-            included: HashSet::new(),
+            included: IndexMap::new(),
+            scanned_dirs: Vec::new(),
+            include_stack: Vec::new(),
+            include_diagnostics: Vec::new(),
+            imports: Vec::new(),
+            import_stack: Vec::new(),
+            loader: Box::new(loader),
+            include_paths: Vec::new(),
+            redefinition_policy: RedefinitionPolicy::default(),
+        }
+    }
+
+    /// Adds a directory searched for `#include <path>` and, when the file is
+    /// not next to the including file, for `#include "path"`. Directories are
+    /// searched in the order they were added.
+    pub fn add_include_path(&mut self, path: impl Into<PathBuf>) {
+        self.include_paths.push(path.into());
+    }
+
+    pub fn include_paths(&self) -> &[PathBuf] {
+        &self.include_paths
+    }
+
+    pub fn set_redefinition_policy(&mut self, policy: RedefinitionPolicy) {
+        self.redefinition_policy = policy;
+    }
+
+    pub fn redefinition_policy(&self) -> RedefinitionPolicy {
+        self.redefinition_policy
+    }
+
+    /// Applies the redefinition policy when `name` was already declared by a
+    /// different `declaration`, and records where it is declared now.
+    fn check_redefinition(
+        &self,
+        definitions: &mut HashMap<String, (String, SourceSpan)>,
+        context: &mut TycoContext,
+        name: String,
+        declaration: String,
+        span: &SourceSpan,
+    ) -> Result<(), TycoError> {
+        if let Some((previous, previous_span)) = definitions.get(&name) {
+            if *previous != declaration {
+                let message = format!(
+                    "{name} is already defined at {}",
+                    describe_site(previous_span)
+                );
+                match self.redefinition_policy {
+                    RedefinitionPolicy::Error => {
+                        return Err(TycoError::parse_with_span(message, span.clone()));
+                    }
+                    RedefinitionPolicy::Warn => context.push_diagnostic(Diagnostic {
+                        message,
+                        span: Some(span.clone()),
+                        previous: Some(previous_span.clone()),
+                    }),
+                    RedefinitionPolicy::Override => {}
+                }
+            }
         }
+        definitions.insert(name, (declaration, span.clone()));
+        Ok(())
     }
 
-    fn is_valid_field_name(name: &str) -> bool {
+    pub(crate) fn is_valid_field_name(name: &str) -> bool {
         let mut chars = name.chars();
         match chars.next() {
             Some(first) if first.is_ascii_alphabetic() || first == '_' => {
@@ -95,8 +203,93 @@ impl TycoParser {
     }
 
     pub fn parse_file<P: AsRef<Path>>(&mut self, path: P) -> Result<TycoContext, TycoError> {
-        let lines = self.read_file_with_includes(path.as_ref())?;
-        self.parse_lines(&lines)
+        self.import_stack.clear();
+        let mut context = self.parse_source_file(path.as_ref(), TycoContext::new())?;
+        context.render()?;
+        Ok(context)
+    }
+
+    /// Parses a base file followed by overlay files and merges them in order,
+    /// as described on `TycoContext::overlay`. Each overlay is parsed against
+    /// the struct schemas of the layers before it, so it may add or update
+    /// instances without repeating the schema. `layer_of` reports the index
+    /// into `paths` of the layer that set each value.
+    pub fn parse_layers<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<TycoContext, TycoError> {
+        self.import_stack.clear();
+        let mut context = TycoContext::new();
+        for (layer, path) in paths.iter().enumerate() {
+            let parsed = self.parse_source_file(path.as_ref(), context.schema_only())?;
+            context.merge_layer(parsed, layer);
+        }
+        context.render()?;
+        context.record_default_layers();
+        Ok(context)
+    }
+
+    /// Parses a file and its includes into `context` without rendering it.
+    /// Every `#import` is parsed and rendered on its own first and merged
+    /// into the context under its alias.
+    fn parse_source_file(
+        &mut self,
+        path: &Path,
+        context: TycoContext,
+    ) -> Result<TycoContext, TycoError> {
+        let canonical = self.loader.canonicalize(path)?;
+        if let Some(start) = self.import_stack.iter().position(|open| *open == canonical) {
+            let mut chain = self.import_stack[start..].to_vec();
+            chain.push(canonical);
+            return Err(TycoError::IncludeCycle(chain));
+        }
+        self.import_stack.push(canonical);
+        let result = self.parse_source_file_with_imports(path, context);
+        self.import_stack.pop();
+        result
+    }
+
+    fn parse_source_file_with_imports(
+        &mut self,
+        path: &Path,
+        mut context: TycoContext,
+    ) -> Result<TycoContext, TycoError> {
+        self.included.clear();
+        self.scanned_dirs.clear();
+        self.include_stack.clear();
+        self.include_diagnostics.clear();
+        self.imports.clear();
+        let lines = self.read_file_with_includes(path, None)?;
+        let include_diagnostics = std::mem::take(&mut self.include_diagnostics);
+        let mut source_files = self
+            .included
+            .iter()
+            .map(|(path, included_from)| SourceFile {
+                path: path.clone(),
+                included_from: included_from.clone(),
+            })
+            .collect::<Vec<_>>();
+        let mut source_dirs = std::mem::take(&mut self.scanned_dirs);
+
+        for import in std::mem::take(&mut self.imports) {
+            let mut imported = self.parse_source_file(&import.path, TycoContext::new())?;
+            imported.render()?;
+            let mut imported_files = imported.source_files().to_vec();
+            if let Some(file) = imported_files.first_mut() {
+                file.included_from.push(import.line.span());
+            }
+            merge_source_files(&mut source_files, imported_files);
+            for dir in imported.source_dirs() {
+                if !source_dirs.contains(dir) {
+                    source_dirs.push(dir.clone());
+                }
+            }
+            context.import_namespace(&import.alias, imported);
+        }
+
+        for diagnostic in include_diagnostics {
+            context.push_diagnostic(diagnostic);
+        }
+        let mut context = self.parse_lines(&lines, context)?;
+        context.set_source_files(source_files, source_dirs);
+        Ok(context)
     }
 
     pub fn parse_str(&mut self, content: &str) -> Result<TycoContext, TycoError> {
@@ -105,42 +298,215 @@ impl TycoParser {
             .enumerate()
             .map(|(idx, line)| SourceLine::new(line.to_string(), None, idx + 1))
             .collect::<Vec<_>>();
-        self.parse_lines(&lines)
+        let mut context = self.parse_lines(&lines, TycoContext::new())?;
+        context.render()?;
+        Ok(context)
     }
 
-    fn read_file_with_includes(&mut self, path: &Path) -> Result<Vec<SourceLine>, TycoError> {
-        let canonical = fs::canonicalize(path)?;
-        if !self.included.insert(canonical.clone()) {
-            return Ok(Vec::new());
+    /// Reads `path` and splices in its includes. `directive` is the include
+    /// line that named the file and whether it was an `#include_once`.
+    fn read_file_with_includes(
+        &mut self,
+        path: &Path,
+        directive: Option<(&SourceLine, bool)>,
+    ) -> Result<Vec<SourceLine>, TycoError> {
+        let canonical = self.loader.canonicalize(path)?;
+        let include_once = directive.is_some_and(|(_, once)| once);
+        if include_once {
+            if let Some(sites) = self.included.get_mut(&canonical) {
+                sites.extend(directive.map(|(line, _)| line.span()));
+                return Ok(Vec::new());
+            }
+        }
+        if let Some(start) = self.include_stack.iter().position(|open| *open == canonical) {
+            let mut chain = self.include_stack[start..].to_vec();
+            chain.push(canonical);
+            return Err(TycoError::IncludeCycle(chain));
+        }
+        if let Some(sites) = self.included.get(&canonical) {
+            let first_site = sites.first().map_or_else(String::new, |span| {
+                let file = span
+                    .path
+                    .as_ref()
+                    .map_or_else(String::new, |path| format!("{}:", path.display()));
+                format!(" (first included at {file}{})", span.line)
+            });
+            let message = format!(
+                "'{}' is already included{first_site}; use #include_once to skip repeated includes",
+                canonical.display()
+            );
+            let error = TycoError::parse(message);
+            return Err(match directive {
+                Some((line, _)) => error.with_span(line.span()),
+                None => error,
+            });
         }
-        let content = fs::read_to_string(&canonical)?;
+        self.included.insert(
+            canonical.clone(),
+            directive.map(|(line, _)| line.span()).into_iter().collect(),
+        );
+        self.include_stack.push(canonical.clone());
+
+        let content = self.loader.read_to_string(&canonical)?;
         let mut result = Vec::new();
-        let parent = canonical
-            .parent()
-            .map(Path::to_path_buf)
-            .unwrap_or_else(|| PathBuf::from("."));
         for (idx, line) in content.lines().enumerate() {
             let source_line =
                 SourceLine::new(line.to_string(), Some(canonical.clone()), idx + 1);
-            if let Some(include_path) = line.trim().strip_prefix("#include") {
-                let include = include_path.trim().trim_matches(|c| c == '"' || c == '\'');
-                let include_full = parent.join(include);
-                match self.read_file_with_includes(&include_full) {
-                    Ok(nested) => result.extend(nested),
-                    Err(err) => return Err(err.with_span(source_line.span())),
+            let trimmed = line.trim();
+            if let Some(import) = trimmed.strip_prefix("#import") {
+                let import = self.resolve_import(&canonical, import.trim(), &source_line)?;
+                self.imports.push(import);
+                continue;
+            }
+            let (include_path, once) = match trimmed.strip_prefix("#include_once") {
+                Some(rest) => (Some(rest), true),
+                None => (trimmed.strip_prefix("#include"), false),
+            };
+            if let Some(include_path) = include_path {
+                let include_files = self
+                    .resolve_include(&canonical, include_path.trim())
+                    .map_err(|err| err.with_span(source_line.span()))?;
+                if include_files.is_empty() {
+                    self.include_diagnostics.push(Diagnostic {
+                        message: format!("Include {} matched no files", include_path.trim()),
+                        span: Some(source_line.span()),
+                        previous: None,
+                    });
+                }
+                for include_full in include_files {
+                    result.extend(
+                        self.read_file_with_includes(&include_full, Some((&source_line, once)))?,
+                    );
                 }
             } else {
                 result.push(source_line);
             }
         }
+        self.include_stack.pop();
         Ok(result)
     }
 
-    fn parse_lines(&mut self, lines: &[SourceLine]) -> Result<TycoContext, TycoError> {
-        let mut context = TycoContext::new();
+    /// Parses the `"file.tyco" as alias` part of an `#import` directive.
+    fn resolve_import(
+        &mut self,
+        including: &Path,
+        directive: &str,
+        line: &SourceLine,
+    ) -> Result<Import, TycoError> {
+        let (target, alias) = directive
+            .rsplit_once(" as ")
+            .map(|(target, alias)| (target.trim(), alias.trim()))
+            .filter(|(_, alias)| {
+                alias.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            })
+            .ok_or_else(|| {
+                TycoError::parse_with_span(
+                    "Import must be written as #import \"file.tyco\" as name",
+                    line.span(),
+                )
+            })?;
+        if self.imports.iter().any(|import| import.alias == alias) {
+            return Err(TycoError::parse_with_span(
+                format!("Namespace '{alias}' is already imported"),
+                line.span(),
+            ));
+        }
+        let mut files = self
+            .resolve_include(including, target)
+            .map_err(|err| err.with_span(line.span()))?;
+        if files.len() != 1 {
+            return Err(TycoError::parse_with_span(
+                format!("Import must name a single file: {target}"),
+                line.span(),
+            ));
+        }
+        Ok(Import {
+            alias: alias.to_string(),
+            path: files.remove(0),
+            line: line.clone(),
+        })
+    }
+
+    /// Finds the files named by an `#include` directive in the file
+    /// `including`. Quoted paths are looked up next to the including file
+    /// first; `<path>` only searches the include paths. A directory, or a
+    /// file name containing `*` or `?`, expands to every matching file other
+    /// than the including file, in sorted order; a bare directory matches
+    /// `*.tyco`. Wildcards are not allowed in directory names. Listed
+    /// directories are recorded for `TycoContext::source_dirs`.
+    fn resolve_include(&mut self, including: &Path, target: &str) -> Result<Vec<PathBuf>, TycoError> {
+        let (include, search_parent) = match target
+            .strip_prefix('<')
+            .and_then(|rest| rest.strip_suffix('>'))
+        {
+            Some(include) => (include.trim(), false),
+            None => (target.trim_matches(|c| c == '"' || c == '\''), true),
+        };
+
+        let (dir, pattern) = match include.rsplit_once('/') {
+            Some((dir, name)) if name.contains(['*', '?']) => (dir, Some(name)),
+            None if include.contains(['*', '?']) => ("", Some(include)),
+            _ => (include, None),
+        };
+        if dir.contains(['*', '?']) {
+            return Err(TycoError::parse(format!(
+                "Wildcards are only allowed in the file name of an include: '{include}'"
+            )));
+        }
+        let parent = including
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let bases = search_parent
+            .then_some(parent)
+            .into_iter()
+            .chain(self.include_paths.iter().cloned());
+        for base in bases {
+            let candidate = base.join(dir);
+            if self.loader.is_dir(&candidate) {
+                let dir = self
+                    .loader
+                    .canonicalize(&candidate)
+                    .unwrap_or_else(|_| candidate.components().collect());
+                if !self.scanned_dirs.contains(&dir) {
+                    self.scanned_dirs.push(dir);
+                }
+                let pattern = pattern.unwrap_or("*.tyco");
+                let mut matches = self
+                    .loader
+                    .list_dir(&candidate)?
+                    .into_iter()
+                    .filter(|path| {
+                        path.file_name()
+                            .and_then(|name| name.to_str())
+                            .is_some_and(|name| wildcard_match(pattern, name))
+                    })
+                    .filter(|path| {
+                        self.loader.canonicalize(path).ok().as_deref() != Some(including)
+                    })
+                    .collect::<Vec<_>>();
+                matches.sort();
+                return Ok(matches);
+            }
+            if pattern.is_none() && self.loader.canonicalize(&candidate).is_ok() {
+                return Ok(vec![candidate]);
+            }
+        }
+        Err(TycoError::parse(format!(
+            "Included file '{include}' not found"
+        )))
+    }
+
+    fn parse_lines(
+        &mut self,
+        lines: &[SourceLine],
+        mut context: TycoContext,
+    ) -> Result<TycoContext, TycoError> {
         let mut state = ParseState::TopLevel;
         let mut current_struct: Option<String> = None;
-        let mut instance_lines: Vec<String> = Vec::new();
+        let mut instance_lines: Vec<(String, SourceSpan)> = Vec::new();
+        let mut definitions: HashMap<String, (String, SourceSpan)> = HashMap::new();
 
         let mut idx = 0;
         while idx < lines.len() {
@@ -170,14 +536,18 @@ impl TycoParser {
             }
 
             if let Some(caps) = FIELD_RE.captures(&line.text) {
-                let is_primary = caps.get(1).map_or(false, |m| m.as_str() == "*");
-                let is_nullable = caps.get(1).map_or(false, |m| m.as_str() == "?");
+                let is_primary = caps.get(1).is_some_and(|m| m.as_str() == "*");
+                let is_nullable = caps.get(1).is_some_and(|m| m.as_str() == "?");
                 let type_name = caps[2].to_string();
                 let is_array = caps.get(3).is_some();
                 let attr_name = caps[4].to_string();
                 let mut value_str =
                     caps.get(5).map(|m| m.as_str().to_string()).unwrap_or_default();
                 let line_span = line.span();
+                let value_span = caps.get(5).map_or_else(
+                    || line_span.clone(),
+                    |m| line.span_at_column(line.text[..m.start()].chars().count() + 1),
+                );
 
                 if has_unclosed_delimiter(&value_str, "\"\"\"")
                     || has_unclosed_delimiter(&value_str, "'''")
@@ -192,7 +562,13 @@ impl TycoParser {
 
                 value_str = strip_inline_comment(&value_str);
 
-                let is_global_line = line.text.chars().next().map_or(false, |c| !c.is_whitespace());
+                let is_global_line = line.text.chars().next().is_some_and(|c| !c.is_whitespace());
+                let declaration = format!(
+                    "{}{type_name}{} {attr_name}: {}",
+                    caps.get(1).map_or("", |m| m.as_str()),
+                    if is_array { "[]" } else { "" },
+                    value_str.trim()
+                );
                 if !is_global_line && current_struct.is_none() {
                     return Err(
                         TycoError::parse("Struct field defined before struct header")
@@ -202,25 +578,77 @@ impl TycoParser {
 
                 if !is_global_line {
                     let struct_name = current_struct.as_ref().unwrap().clone();
+                    self.check_redefinition(
+                        &mut definitions,
+                        &mut context,
+                        format!("Field '{struct_name}.{attr_name}'"),
+                        declaration,
+                        &line_span,
+                    )?;
                     let mut field = FieldSchema::new(&attr_name, &type_name);
                     field.is_primary_key = is_primary;
                     field.is_nullable = is_nullable;
                     field.is_array = is_array;
-                    if !value_str.is_empty() {
+                    let has_default = !value_str.is_empty();
+                    if has_default {
                         let ty = field_type_name(&field);
                         let parsed =
-                            self.parse_value(&value_str, &ty, &context, &line_span)?;
+                            self.parse_value(&value_str, &ty, &context, &value_span)?;
                         field.default_value = Some(parsed);
+                        if is_append(&value_str) {
+                            context.mark_append(format!("{struct_name}.{attr_name}"));
+                        }
                     }
-                    context
+                    let struct_def = context
                         .get_struct_mut(&struct_name)
-                        .ok_or_else(|| TycoError::UnknownStruct(struct_name.clone()))?
-                        .add_field(field);
+                        .ok_or_else(|| TycoError::UnknownStruct(struct_name.clone()))?;
+                    // Instances already parsed hold values of the old type.
+                    let retyped = struct_def.fields().iter().find(|existing| {
+                        existing.name == attr_name
+                            && (existing.type_name != type_name || existing.is_array != is_array)
+                    });
+                    if let (Some(existing), false) = (retyped, struct_def.instances().is_empty()) {
+                        return Err(TycoError::parse_with_span(
+                            format!(
+                                "Field '{struct_name}.{attr_name}' cannot change type from {} to {} after {struct_name} instances are declared",
+                                field_type_name(existing),
+                                field_type_name(&field)
+                            ),
+                            line_span,
+                        ));
+                    }
+                    struct_def.add_field(field);
+                    if has_default {
+                        struct_def.set_default_origin(
+                            attr_name,
+                            ValueOrigin::new(OriginKind::Default, value_span),
+                        );
+                    }
                     state = ParseState::InStructSchema;
                 } else {
+                    self.check_redefinition(
+                        &mut definitions,
+                        &mut context,
+                        format!("Global '{attr_name}'"),
+                        declaration,
+                        &line_span,
+                    )?;
                     let type_descriptor = field_type_descriptor(&type_name, is_array);
                     let value =
-                        self.parse_value(&value_str, &type_descriptor, &context, &line_span)?;
+                        self.parse_value(&value_str, &type_descriptor, &context, &value_span)?;
+                    if is_append(&value_str) {
+                        context.mark_append(attr_name.clone());
+                    }
+                    context.set_global_origin(
+                        attr_name.clone(),
+                        ValueOrigin::new(OriginKind::Explicit, value_span),
+                    );
+                    let declared = if is_nullable {
+                        format!("?{type_descriptor}")
+                    } else {
+                        type_descriptor
+                    };
+                    context.set_global_type(attr_name.clone(), declared);
                     context.set_global(attr_name, value);
                     state = ParseState::TopLevel;
                 }
@@ -262,11 +690,23 @@ impl TycoParser {
                         let ty = field_type_name(field_schema);
                         Some(self.parse_value(&value_str, &ty, &context, &value_span)?)
                     };
+                    if is_append(&value_str) {
+                        context.mark_append(format!("{struct_name}.{field_name}"));
+                    }
 
-                    context
+                    let origin = parsed_value.is_some().then(|| {
+                        ValueOrigin::new(
+                            OriginKind::Default,
+                            literal_span(&value_span, value_str.trim()),
+                        )
+                    });
+                    let struct_def = context
                         .get_struct_mut(struct_name)
-                        .ok_or_else(|| TycoError::UnknownStruct(struct_name.clone()))?
-                        .set_default(&field_name, parsed_value)?;
+                        .ok_or_else(|| TycoError::UnknownStruct(struct_name.clone()))?;
+                    struct_def.set_default(&field_name, parsed_value)?;
+                    if let Some(origin) = origin {
+                        struct_def.set_default_origin(field_name, origin);
+                    }
                     idx += 1;
                     continue;
                 }
@@ -297,15 +737,15 @@ impl TycoParser {
                     };
                     idx = Self::accumulate_multiline(idx, lines, &mut inst_line, delimiter);
                 }
-                instance_lines.push(inst_line);
+                instance_lines.push((inst_line, line.span()));
                 idx += 1;
                 continue;
             }
 
             if state == ParseState::InStructInstances
-                && line.text.chars().next().map_or(false, |c| c.is_whitespace())
+                && line.text.chars().next().is_some_and(|c| c.is_whitespace())
             {
-                if let Some(last) = instance_lines.last_mut() {
+                if let Some((last, _)) = instance_lines.last_mut() {
                     last.push(' ');
                     last.push_str(trimmed_ws);
                 }
@@ -322,7 +762,6 @@ impl TycoParser {
             }
         }
 
-        context.render()?;
         Ok(context)
     }
 
@@ -347,7 +786,7 @@ impl TycoParser {
     fn parse_struct_instances(
         &self,
         struct_name: &str,
-        instance_lines: &[String],
+        instance_lines: &[(String, SourceSpan)],
         context: &mut TycoContext,
     ) -> Result<(), TycoError> {
         if instance_lines.is_empty() {
@@ -360,23 +799,22 @@ impl TycoParser {
             .fields()
             .to_vec();
 
-        for line in instance_lines {
+        for (line, line_span) in instance_lines {
             let parts = split_top_level(line, ',');
             let mut instance = TycoInstance::new(struct_name);
             let mut positional_index = 0;
             let mut using_named = false;
-            let line_span = SourceSpan {
-                path: None,
-                line: 0,
-                column: 1,
-                line_text: line.clone(),
-            };
+            let mut appended = Vec::new();
+            let mut cursor = line_span.clone();
             for part in parts {
                 let part = part.trim();
                 if part.is_empty() {
                     continue;
                 }
-                if let Some((field, value)) = Self::split_named_argument(&part) {
+                cursor = literal_span(&cursor, part);
+                let part_span = cursor.clone();
+                cursor.column += part.chars().count();
+                if let Some((field, value)) = Self::split_named_argument(part) {
                     using_named = true;
                     let schema = fields
                         .iter()
@@ -384,7 +822,17 @@ impl TycoParser {
                         .ok_or_else(|| TycoError::parse(format!("Unknown field '{field}' in {struct_name}")).with_span(line_span.clone()))?;
                     let ty = field_type_name(schema);
                     let typed_value =
-                        self.parse_value(value.trim(), &ty, context, &line_span)?;
+                        self.parse_value(value.trim(), &ty, context, line_span)?;
+                    if is_append(value) {
+                        appended.push(field.to_string());
+                    }
+                    instance.set_origin(
+                        field.to_string(),
+                        ValueOrigin::new(
+                            OriginKind::Explicit,
+                            literal_span(&part_span, value.trim()),
+                        ),
+                    );
                     instance.set_attribute(field.to_string(), typed_value);
                 } else {
                     if using_named {
@@ -405,7 +853,14 @@ impl TycoParser {
                     }
                     let schema = &fields[positional_index];
                     let ty = field_type_name(schema);
-                    let typed_value = self.parse_value(part, &ty, context, &line_span)?;
+                    let typed_value = self.parse_value(part, &ty, context, line_span)?;
+                    if is_append(part) {
+                        appended.push(schema.name.clone());
+                    }
+                    instance.set_origin(
+                        schema.name.clone(),
+                        ValueOrigin::new(OriginKind::Positional, part_span),
+                    );
                     instance.set_attribute(schema.name.clone(), typed_value);
                     positional_index += 1;
                 }
@@ -414,7 +869,11 @@ impl TycoParser {
             let struct_mut = context
                 .get_struct_mut(struct_name)
                 .ok_or_else(|| TycoError::UnknownStruct(struct_name.to_string()))?;
+            let path = struct_mut.instance_path(struct_mut.instances().len(), &instance);
             struct_mut.add_instance(instance);
+            for field in appended {
+                context.mark_append(format!("{path}.{field}"));
+            }
         }
 
         Ok(())
@@ -468,37 +927,17 @@ impl TycoParser {
         if trimmed.eq_ignore_ascii_case("null") {
             return Ok(TycoValue::Null);
         }
+        if let Some(result) = parse_scalar(trimmed, type_name) {
+            return result.map_err(|e| e.with_span(literal_span(span, trimmed)));
+        }
         match type_name {
-            "bool" => {
-                if trimmed == "true" {
-                    Ok(TycoValue::Bool(true))
-                } else if trimmed == "false" {
-                    Ok(TycoValue::Bool(false))
-                } else {
-                    Err(
-                        TycoError::parse(format!("Invalid bool literal '{trimmed}'"))
-                            .with_span(span.clone()),
-                    )
-                }
-            }
-            "int" => Ok(TycoValue::Int(
-                parse_integer(trimmed).map_err(|e| e.with_span(span.clone()))?,
-            )),
-            "float" => {
-                let value = trimmed.parse::<f64>().map_err(|e| {
-                    TycoError::parse(format!("Invalid float literal '{trimmed}': {e}"))
-                        .with_span(span.clone())
-                })?;
-                Ok(TycoValue::Float(value))
-            }
-            "date" => Ok(TycoValue::Date(parse_string_value(trimmed)?.value)),
-            "time" => Ok(TycoValue::Time(normalize_time(&parse_string_value(trimmed)?.value))),
-            "datetime" => Ok(TycoValue::DateTime(normalize_datetime(
-                &parse_string_value(trimmed)?.value,
-            ))),
-            "str" => Ok(TycoValue::String(parse_string_value(trimmed)?)),
             _ if type_name.ends_with("[]") => {
                 let base = &type_name[..type_name.len() - 2];
+                // `+[...]` marks an array that appends to an earlier layer.
+                let trimmed = trimmed
+                    .strip_prefix('+')
+                    .filter(|rest| rest.starts_with('['))
+                    .unwrap_or(trimmed);
                 if trimmed == "[]" {
                     return Ok(TycoValue::Array(Vec::new()));
                 }
@@ -537,7 +976,14 @@ impl TycoParser {
             let args = caps[2].to_string();
             match context.get_struct(&struct_name) {
                 Some(def) if def.has_primary_key() => {
-                    let pk = parse_string_value(args.trim())?.value;
+                    let pk = def
+                        .normalize_key(&parse_reference_key(&args)?)
+                        .map_err(|err| match err {
+                            TycoError::Reference(message) => {
+                                TycoError::parse_with_span(message, literal_span(span, token))
+                            }
+                            other => other.with_span(literal_span(span, token)),
+                        })?;
                     return Ok(TycoValue::Reference(TycoReference::new(struct_name, pk)));
                 }
                 Some(_) => {
@@ -545,7 +991,7 @@ impl TycoParser {
                     return Ok(TycoValue::Instance(inline_instance));
                 }
                 None => {
-                    let pk = parse_string_value(args.trim())?.value;
+                    let pk = parse_reference_key(&args)?;
                     return Ok(TycoValue::Reference(TycoReference::new(struct_name, pk)));
                 }
             }
@@ -594,7 +1040,31 @@ pub fn loads(content: &str) -> Result<TycoContext, TycoError> {
     TycoParser::new().parse_str(content)
 }
 
-fn field_type_name(field: &FieldSchema) -> String {
+/// Whether an array value is written as `+[...]`.
+pub(crate) fn is_append(token: &str) -> bool {
+    token.trim_start().starts_with("+[")
+}
+
+/// `file:line`, or `line N` for sources parsed from a string.
+fn describe_site(span: &SourceSpan) -> String {
+    match &span.path {
+        Some(path) => format!("{}:{}", path.display(), span.line),
+        None => format!("line {}", span.line),
+    }
+}
+
+/// Parses an override value given on the command line or through
+/// `TycoContext::apply_override`, exactly as it would be parsed in a file.
+pub(crate) fn parse_override_value(
+    token: &str,
+    type_name: &str,
+    context: &TycoContext,
+    span: &SourceSpan,
+) -> Result<TycoValue, TycoError> {
+    TycoParser::new().parse_value(token, type_name, context, span)
+}
+
+pub(crate) fn field_type_name(field: &FieldSchema) -> String {
     field_type_descriptor(&field.type_name, field.is_array)
 }
 
@@ -606,19 +1076,79 @@ fn field_type_descriptor(base: &str, is_array: bool) -> String {
     }
 }
 
+/// Parses `token` as a builtin scalar type, returning `None` when `type_name`
+/// is not one (arrays and struct types are handled by the parser itself).
+pub(crate) fn parse_scalar(token: &str, type_name: &str) -> Option<Result<TycoValue, TycoError>> {
+    let value = match type_name {
+        "bool" => match token {
+            "true" => Ok(TycoValue::Bool(true)),
+            "false" => Ok(TycoValue::Bool(false)),
+            _ => Err(TycoError::parse(format!("Invalid bool literal '{token}'"))),
+        },
+        "int" => parse_integer(token).map(TycoValue::Int),
+        "uint" | "u64" => parse_unsigned(token).map(TycoValue::UInt),
+        #[cfg(feature = "bigint")]
+        "bigint" => parse_big_integer(token).map(TycoValue::BigInt),
+        "decimal" => parse_decimal(token).map(TycoValue::Decimal),
+        "float" => parse_float(token).map(TycoValue::Float),
+        "date" => parse_string_value(token).map(|s| TycoValue::Date(s.value)),
+        "time" => parse_string_value(token).map(|s| TycoValue::Time(normalize_time(&s.value))),
+        "datetime" => {
+            parse_string_value(token).map(|s| TycoValue::DateTime(normalize_datetime(&s.value)))
+        }
+        "str" => parse_string_value(token).map(TycoValue::String),
+        "bytes" => parse_bytes_literal(token).map(TycoValue::Bytes),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Whether `type_name` is one of the builtin scalar types `parse_scalar`
+/// handles, rather than a struct name.
+pub(crate) fn is_scalar_type(type_name: &str) -> bool {
+    matches!(
+        type_name,
+        "bool" | "int" | "uint" | "u64" | "decimal" | "float" | "date" | "time" | "datetime"
+            | "str" | "bytes"
+    ) || (cfg!(feature = "bigint") && type_name == "bigint")
+}
+
+pub(crate) fn parse_reference_key(args: &str) -> Result<PrimaryKey, TycoError> {
+    split_top_level(args, ',')
+        .iter()
+        .map(|part| parse_string_value(part.trim()).map(TycoValue::String))
+        .collect::<Result<Vec<_>, _>>()
+        .map(PrimaryKey::new)
+}
+
+/// Narrows `span` to the first occurrence of `literal` on its line, so errors
+/// inside arrays and instance rows point at the offending literal.
+fn literal_span(span: &SourceSpan, literal: &str) -> SourceSpan {
+    let mut narrowed = span.clone();
+    let search_from = span
+        .line_text
+        .char_indices()
+        .nth(span.column.saturating_sub(1))
+        .map_or(0, |(idx, _)| idx);
+    if let Some(offset) = span.line_text[search_from..].find(literal) {
+        narrowed.column = span.line_text[..search_from + offset].chars().count() + 1;
+    }
+    narrowed
+}
+
 fn parse_string_value(token: &str) -> Result<TycoString, TycoError> {
-    if token.starts_with("\"\"\"") {
-        if let Some(end) = token[3..].find("\"\"\"") {
-            let raw = &token[3..3 + end];
+    if let Some(rest) = token.strip_prefix("\"\"\"") {
+        if let Some(end) = rest.find("\"\"\"") {
+            let raw = &rest[..end];
             let content = strip_leading_newline(raw);
             let content = unescape_basic_string(&content)?;
             let has_template = content.contains('{') && content.contains('}');
             return Ok(TycoString::new(content, has_template, false));
         }
     }
-    if token.starts_with("'''") {
-        if let Some(end) = token[3..].find("'''") {
-            let content = token[3..3 + end].to_string();
+    if let Some(rest) = token.strip_prefix("'''") {
+        if let Some(end) = rest.find("'''") {
+            let content = rest[..end].to_string();
             return Ok(TycoString::new(content, false, true));
         }
     }
@@ -0,0 +1,443 @@
+//! Paths naming values in a rendered context: `timezone`,
+//! `Host[prod-01].cores`, `Application[0].host.hostname`, `ports[1]`, and
+//! the selectors `TycoContext::query` understands, `Application[*]` and
+//! `Application[?host.os == "Fedora"]`.
+
+use std::{borrow::Cow, cmp::Ordering};
+
+use serde_json::Value as JsonValue;
+
+use crate::{
+    context::TycoContext,
+    error::TycoError,
+    value::{TycoInstance, TycoValue},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PathSegment {
+    /// A global, struct or field name.
+    Name(String),
+    /// The text between `[` and `]`: a primary key or an array index.
+    Index(String),
+    /// `[*]`: every instance or array item.
+    Wildcard,
+    /// `[?path op literal]`: the instances or items matching a condition.
+    Filter(Filter),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Filter {
+    path: Vec<PathSegment>,
+    /// `None` for `[?path]`, which matches when the value is neither null
+    /// nor false.
+    comparison: Option<(Comparison, Operand<'static>)>,
+}
+
+/// A side of a filter comparison. Numbers of every type compare by value,
+/// integers exactly and against floats as `f64`; strings, dates, times and
+/// bytes compare as text.
+#[derive(Clone, Debug, PartialEq)]
+enum Operand<'a> {
+    Null,
+    Bool(bool),
+    Integer(i128),
+    Number(f64),
+    Text(Cow<'a, str>),
+    /// Arrays and instances, which only match `!=`.
+    Other,
+}
+
+impl<'a> From<&'a TycoValue> for Operand<'a> {
+    fn from(value: &'a TycoValue) -> Self {
+        match value {
+            TycoValue::Null => Operand::Null,
+            TycoValue::Bool(v) => Operand::Bool(*v),
+            TycoValue::Int(v) => Operand::Integer(i128::from(*v)),
+            TycoValue::UInt(v) => Operand::Integer(i128::from(*v)),
+            TycoValue::Float(v) if v.is_finite() => Operand::Number(*v),
+            #[cfg(feature = "bigint")]
+            TycoValue::BigInt(v) => number_or_text(v.to_string()),
+            TycoValue::Decimal(v) => number_or_text(v.clone()),
+            TycoValue::String(s) => Operand::Text(Cow::Borrowed(&s.value)),
+            TycoValue::Date(v) | TycoValue::Time(v) | TycoValue::DateTime(v) => {
+                Operand::Text(Cow::Borrowed(v))
+            }
+            TycoValue::Array(_) | TycoValue::Instance(_) | TycoValue::Reference(_) => {
+                Operand::Other
+            }
+            other => Operand::Text(Cow::Owned(other.to_template_text())),
+        }
+    }
+}
+
+fn number_or_text(literal: String) -> Operand<'static> {
+    if let Ok(integer) = literal.parse::<i128>() {
+        return Operand::Integer(integer);
+    }
+    match literal.parse::<f64>() {
+        Ok(number) => Operand::Number(number),
+        Err(_) => Operand::Text(Cow::Owned(literal)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A value selected by `TycoContext::get` or `TycoContext::query`.
+#[derive(Clone, Copy, Debug)]
+pub enum PathMatch<'a> {
+    /// A global, a field value, a struct default or an array item.
+    /// References are returned as written; paths continuing past them
+    /// (`Application[web].host.os`) follow them to the target instance.
+    Value(&'a TycoValue),
+    /// A struct instance, selected with `Host[prod-01]`, `Host[*]` or a filter.
+    Instance(&'a TycoInstance),
+}
+
+impl<'a> PathMatch<'a> {
+    pub fn value(&self) -> Option<&'a TycoValue> {
+        match *self {
+            PathMatch::Value(value) => Some(value),
+            PathMatch::Instance(_) => None,
+        }
+    }
+
+    /// The selected instance, or the inline instance held by a value.
+    pub fn instance(&self) -> Option<&'a TycoInstance> {
+        match *self {
+            PathMatch::Value(TycoValue::Instance(instance)) | PathMatch::Instance(instance) => {
+                Some(instance)
+            }
+            PathMatch::Value(_) => None,
+        }
+    }
+
+    pub fn to_json_value(&self, ctx: &TycoContext) -> JsonValue {
+        match self {
+            PathMatch::Value(value) => value.to_json_value_in(ctx),
+            PathMatch::Instance(instance) => instance.to_json_value(ctx),
+        }
+    }
+}
+
+pub(crate) fn parse_path(path: &str) -> Result<Vec<PathSegment>, TycoError> {
+    let invalid = || TycoError::parse(format!("Invalid path '{path}'"));
+    let mut segments = Vec::new();
+    let mut name = String::new();
+    let mut chars = path.trim().chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '.' => {
+                if name.is_empty() && matches!(segments.last(), None | Some(PathSegment::Name(_))) {
+                    return Err(invalid());
+                }
+                if !name.is_empty() {
+                    segments.push(PathSegment::Name(std::mem::take(&mut name)));
+                }
+                if chars.peek().is_none() {
+                    return Err(invalid());
+                }
+            }
+            '[' => {
+                if !name.is_empty() {
+                    segments.push(PathSegment::Name(std::mem::take(&mut name)));
+                } else if segments.is_empty() {
+                    return Err(invalid());
+                }
+                let mut selector = String::new();
+                let mut depth = 0usize;
+                let mut quote = None;
+                loop {
+                    match chars.next() {
+                        None => return Err(invalid()),
+                        Some('\\') if quote == Some('"') => {
+                            selector.push('\\');
+                            selector.extend(chars.next());
+                        }
+                        Some(ch) if quote.is_some() => {
+                            if Some(ch) == quote {
+                                quote = None;
+                            }
+                            selector.push(ch);
+                        }
+                        Some(ch @ ('"' | '\'')) => {
+                            quote = Some(ch);
+                            selector.push(ch);
+                        }
+                        Some(']') if depth == 0 => break,
+                        Some(ch) => {
+                            match ch {
+                                '[' => depth += 1,
+                                ']' => depth -= 1,
+                                _ => {}
+                            }
+                            selector.push(ch);
+                        }
+                    }
+                }
+                segments.push(parse_selector(selector.trim()).ok_or_else(invalid)?);
+                if !matches!(chars.peek(), None | Some('.') | Some('[')) {
+                    return Err(invalid());
+                }
+            }
+            ']' => return Err(invalid()),
+            ch => name.push(ch),
+        }
+    }
+    if !name.is_empty() {
+        segments.push(PathSegment::Name(name));
+    }
+    if segments.is_empty() {
+        return Err(invalid());
+    }
+    Ok(segments)
+}
+
+fn parse_selector(selector: &str) -> Option<PathSegment> {
+    match selector {
+        "" => None,
+        "*" => Some(PathSegment::Wildcard),
+        _ => match selector.strip_prefix('?') {
+            Some(condition) => parse_filter(condition.trim()).map(PathSegment::Filter),
+            None => Some(PathSegment::Index(selector.to_string())),
+        },
+    }
+}
+
+fn parse_filter(condition: &str) -> Option<Filter> {
+    let Some((start, comparison, len)) = find_comparison(condition) else {
+        return Some(Filter {
+            path: parse_path(condition).ok()?,
+            comparison: None,
+        });
+    };
+    let literal = condition[start + len..].trim();
+    if literal.is_empty() {
+        return None;
+    }
+    Some(Filter {
+        path: parse_path(&condition[..start]).ok()?,
+        comparison: Some((comparison, parse_literal(literal))),
+    })
+}
+
+/// The first comparison operator outside quotes and brackets, with its byte
+/// offset and length.
+fn find_comparison(condition: &str) -> Option<(usize, Comparison, usize)> {
+    let mut depth = 0usize;
+    let mut quote = None;
+    for (idx, ch) in condition.char_indices() {
+        if let Some(open) = quote {
+            if ch == open {
+                quote = None;
+            }
+            continue;
+        }
+        let next = condition[idx + ch.len_utf8()..].chars().next();
+        match (ch, next) {
+            ('"' | '\'', _) => quote = Some(ch),
+            ('[', _) => depth += 1,
+            (']', _) => depth = depth.saturating_sub(1),
+            _ if depth > 0 => {}
+            ('=', Some('=')) => return Some((idx, Comparison::Eq, 2)),
+            ('!', Some('=')) => return Some((idx, Comparison::Ne, 2)),
+            ('<', Some('=')) => return Some((idx, Comparison::Le, 2)),
+            ('>', Some('=')) => return Some((idx, Comparison::Ge, 2)),
+            ('<', _) => return Some((idx, Comparison::Lt, 1)),
+            ('>', _) => return Some((idx, Comparison::Gt, 1)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Filter literals: quoted strings, numbers, `true`, `false` and `null`.
+/// Anything else is compared as a bare string, so `[?os == Fedora]` works.
+fn parse_literal(literal: &str) -> Operand<'static> {
+    if literal.len() >= 2 && literal.starts_with('\'') && literal.ends_with('\'') {
+        return Operand::Text(Cow::Owned(literal[1..literal.len() - 1].to_string()));
+    }
+    if let Ok(integer) = literal.parse::<i128>() {
+        return Operand::Integer(integer);
+    }
+    match serde_json::from_str::<JsonValue>(literal) {
+        Ok(JsonValue::Null) => Operand::Null,
+        Ok(JsonValue::Bool(value)) => Operand::Bool(value),
+        Ok(JsonValue::Number(number)) => number.as_f64().map_or(Operand::Other, Operand::Number),
+        Ok(JsonValue::String(text)) => Operand::Text(Cow::Owned(text)),
+        _ => Operand::Text(Cow::Owned(literal.to_string())),
+    }
+}
+
+/// Splits the longest run of leading names that `is_known` accepts, joined
+/// with dots as namespaced imports are (`net.Port`), from the rest of the path.
+pub(crate) fn split_qualified_name(
+    segments: &[PathSegment],
+    is_known: impl Fn(&str) -> bool,
+) -> Option<(String, &[PathSegment])> {
+    let names = segments
+        .iter()
+        .map_while(|segment| match segment {
+            PathSegment::Name(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    (1..=names.len()).rev().find_map(|end| {
+        let name = names[..end].join(".");
+        is_known(&name).then(|| (name, &segments[end..]))
+    })
+}
+
+pub(crate) fn query<'a>(context: &'a TycoContext, segments: &[PathSegment]) -> Vec<PathMatch<'a>> {
+    let Some((name, rest)) = split_qualified_name(segments, |name| {
+        context.get_struct(name).is_some() || context.globals().contains_key(name)
+    }) else {
+        return Vec::new();
+    };
+    let Some(struct_def) = context.get_struct(&name) else {
+        return walk(
+            context,
+            vec![PathMatch::Value(&context.globals()[&name])],
+            rest,
+        );
+    };
+    let instances = struct_def
+        .instances()
+        .iter()
+        .map(PathMatch::Instance)
+        .collect::<Vec<_>>();
+    match rest.split_first() {
+        None => instances,
+        Some((PathSegment::Name(field), rest)) => {
+            let default = struct_def
+                .fields()
+                .iter()
+                .find(|schema| schema.name == *field)
+                .and_then(|schema| schema.default_value.as_ref());
+            walk(
+                context,
+                default.map(PathMatch::Value).into_iter().collect(),
+                rest,
+            )
+        }
+        Some((selector, rest)) => {
+            let selected = select(context, instances, selector, |key| {
+                struct_def.index_for_path_key(key)
+            });
+            walk(context, selected, rest)
+        }
+    }
+}
+
+fn walk<'a>(
+    context: &'a TycoContext,
+    matches: Vec<PathMatch<'a>>,
+    segments: &[PathSegment],
+) -> Vec<PathMatch<'a>> {
+    segments.iter().fold(matches, |matches, segment| {
+        matches
+            .into_iter()
+            .flat_map(|found| step(context, found, segment))
+            .collect()
+    })
+}
+
+fn step<'a>(
+    context: &'a TycoContext,
+    found: PathMatch<'a>,
+    segment: &PathSegment,
+) -> Vec<PathMatch<'a>> {
+    match (segment, found) {
+        (PathSegment::Name(field), _) => instance_of(context, found)
+            .and_then(|instance| instance.get_attribute(field))
+            .map(PathMatch::Value)
+            .into_iter()
+            .collect(),
+        (_, PathMatch::Value(TycoValue::Array(items))) => {
+            let items = items.iter().map(PathMatch::Value).collect();
+            select(context, items, segment, |key| key.parse::<usize>().ok())
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The instance a path continues into: a selected or inline instance, or the
+/// target of a resolved reference.
+fn instance_of<'a>(context: &'a TycoContext, found: PathMatch<'a>) -> Option<&'a TycoInstance> {
+    match found {
+        PathMatch::Value(TycoValue::Reference(reference)) => context.resolve(reference),
+        _ => found.instance(),
+    }
+}
+
+fn select<'a>(
+    context: &'a TycoContext,
+    candidates: Vec<PathMatch<'a>>,
+    selector: &PathSegment,
+    position: impl Fn(&str) -> Option<usize>,
+) -> Vec<PathMatch<'a>> {
+    match selector {
+        PathSegment::Index(key) => position(key)
+            .and_then(|index| candidates.get(index).copied())
+            .into_iter()
+            .collect(),
+        PathSegment::Wildcard => candidates,
+        PathSegment::Filter(filter) => candidates
+            .into_iter()
+            .filter(|candidate| filter.matches(context, *candidate))
+            .collect(),
+        PathSegment::Name(_) => Vec::new(),
+    }
+}
+
+impl Filter {
+    /// Compares the values the filter path selects directly, without
+    /// converting them (and every instance they reference) to JSON.
+    fn matches(&self, context: &TycoContext, candidate: PathMatch<'_>) -> bool {
+        walk(context, vec![candidate], &self.path)
+            .iter()
+            .any(|found| {
+                let operand = match found {
+                    PathMatch::Value(TycoValue::Reference(reference))
+                        if context.resolve(reference).is_none() =>
+                    {
+                        Operand::Null
+                    }
+                    PathMatch::Value(value) => Operand::from(*value),
+                    PathMatch::Instance(_) => Operand::Other,
+                };
+                match &self.comparison {
+                    None => !matches!(operand, Operand::Null | Operand::Bool(false)),
+                    Some((comparison, literal)) => comparison.holds(&operand, literal),
+                }
+            })
+    }
+}
+
+impl Comparison {
+    fn holds(self, left: &Operand<'_>, right: &Operand<'_>) -> bool {
+        let ordering = match (left, right) {
+            (Operand::Integer(a), Operand::Integer(b)) => Some(a.cmp(b)),
+            (Operand::Integer(a), Operand::Number(b)) => (*a as f64).partial_cmp(b),
+            (Operand::Number(a), Operand::Integer(b)) => a.partial_cmp(&(*b as f64)),
+            (Operand::Number(a), Operand::Number(b)) => a.partial_cmp(b),
+            (Operand::Text(a), Operand::Text(b)) => Some(a.cmp(b)),
+            _ => None,
+        };
+        match self {
+            Comparison::Eq => ordering.map_or(left == right, Ordering::is_eq),
+            Comparison::Ne => !ordering.map_or(left == right, Ordering::is_eq),
+            Comparison::Lt => ordering == Some(Ordering::Less),
+            Comparison::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Comparison::Gt => ordering == Some(Ordering::Greater),
+            Comparison::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! JSON Schema (draft 2020-12) for the documents `TycoContext::to_json`
+//! produces, derived from the declared types of globals and struct fields.
+
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::{
+    builder::parse_declaration,
+    context::{FieldSchema, TycoContext},
+    value::{BytesEncoding, JsonOptions},
+};
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+pub(crate) fn context_schema(context: &TycoContext, options: &JsonOptions) -> JsonValue {
+    let encoder = SchemaEncoder { context, options };
+    let mut properties = Map::new();
+    for name in context.globals().keys() {
+        let schema = context
+            .global_type(name)
+            .and_then(|type_name| parse_declaration(name, type_name).ok())
+            .map(|field| encoder.field(&field, None))
+            .unwrap_or_else(|| json!({}));
+        properties.insert(name.clone(), schema);
+    }
+    for (name, struct_def) in context.structs() {
+        if struct_def.has_primary_key() {
+            properties.insert(
+                name.clone(),
+                json!({"type": "array", "items": {"$ref": definition(name)}}),
+            );
+        }
+    }
+    let required = context
+        .globals()
+        .keys()
+        .chain(
+            context
+                .structs()
+                .iter()
+                .filter_map(|(name, struct_def)| struct_def.has_primary_key().then_some(name)),
+        )
+        .collect::<Vec<_>>();
+
+    let mut definitions = Map::new();
+    for (name, struct_def) in context.structs() {
+        let mut fields = Map::new();
+        for field in struct_def.fields() {
+            let default = field
+                .default_value
+                .as_ref()
+                .map(|value| value.to_json_value_in_with(context, options));
+            fields.insert(field.name.clone(), encoder.field(field, default));
+        }
+        // Nullable fields left unset are omitted from the instance's object;
+        // every other field is written, with its default if need be.
+        let required = struct_def
+            .fields()
+            .iter()
+            .filter(|field| !field.is_nullable)
+            .map(|field| field.name.clone())
+            .collect::<Vec<_>>();
+        definitions.insert(
+            name.clone(),
+            json!({
+                "type": "object",
+                "properties": fields,
+                "required": required,
+                "additionalProperties": false,
+            }),
+        );
+    }
+
+    json!({
+        "$schema": DRAFT,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+        "$defs": definitions,
+    })
+}
+
+struct SchemaEncoder<'a> {
+    context: &'a TycoContext,
+    options: &'a JsonOptions,
+}
+
+impl SchemaEncoder<'_> {
+    fn field(&self, field: &FieldSchema, default: Option<JsonValue>) -> JsonValue {
+        let mut schema = self.scalar(&field.type_name);
+        if field.is_array {
+            schema = json!({"type": "array", "items": schema});
+        }
+        if field.is_nullable {
+            schema = nullable(schema);
+        }
+        if let (Some(default), JsonValue::Object(map)) = (default, &mut schema) {
+            map.insert("default".to_string(), default);
+        }
+        schema
+    }
+
+    fn scalar(&self, type_name: &str) -> JsonValue {
+        match type_name {
+            "str" => json!({"type": "string"}),
+            "bool" => json!({"type": "boolean"}),
+            "int" => json!({"type": "integer"}),
+            "uint" | "u64" => json!({"type": "integer", "minimum": 0}),
+            "float" => json!({"anyOf": [
+                {"type": "number"},
+                {"enum": ["inf", "-inf", "nan"]},
+            ]}),
+            "decimal" => exact_number(),
+            #[cfg(feature = "bigint")]
+            "bigint" => exact_number(),
+            "date" => json!({"type": "string", "format": "date"}),
+            // Times and datetimes may be written without a UTC offset, which
+            // the RFC 3339 `time` and `date-time` formats require.
+            "time" | "datetime" => json!({"type": "string"}),
+            "bytes" => match self.options.bytes {
+                BytesEncoding::Base64 => json!({"type": "string", "contentEncoding": "base64"}),
+                BytesEncoding::Hex => json!({"type": "string", "pattern": "^([0-9a-f]{2})*$"}),
+                BytesEncoding::Array => json!({
+                    "type": "array",
+                    "items": {"type": "integer", "minimum": 0, "maximum": 255},
+                }),
+            },
+            name if self.context.get_struct(name).is_some() => json!({"$ref": definition(name)}),
+            _ => json!({}),
+        }
+    }
+}
+
+/// Decimal and big-integer values are JSON numbers only when serde_json
+/// keeps arbitrary precision; see `exact_number_json`.
+fn exact_number() -> JsonValue {
+    if cfg!(feature = "arbitrary-precision") {
+        json!({"type": "number"})
+    } else {
+        json!({"type": "string"})
+    }
+}
+
+fn nullable(schema: JsonValue) -> JsonValue {
+    json!({"anyOf": [schema, {"type": "null"}]})
+}
+
+fn definition(struct_name: &str) -> String {
+    format!("#/$defs/{struct_name}")
+}
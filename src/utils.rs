@@ -1,12 +1,14 @@
 use std::borrow::Cow;
 
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
 use crate::error::TycoError;
 
 pub fn strip_inline_comment(line: &str) -> String {
     let mut in_quotes = false;
     let mut quote_char = '\0';
 
-    for (idx, ch) in line.chars().enumerate() {
+    for (idx, ch) in line.char_indices() {
         if !in_quotes && (ch == '"' || ch == '\'') {
             in_quotes = true;
             quote_char = ch;
@@ -35,9 +37,7 @@ pub fn split_top_level(input: &str, delimiter: char) -> Vec<String> {
     let mut depth: i32 = 0;
     let mut in_quotes = false;
     let mut quote_char = '\0';
-    let mut chars = input.chars().peekable();
-
-    while let Some(ch) = chars.next() {
+    for ch in input.chars() {
         if in_quotes {
             if ch == quote_char && !is_escaped(&current) {
                 in_quotes = false;
@@ -88,25 +88,6 @@ fn is_escaped(current: &str) -> bool {
     backslashes % 2 == 1
 }
 
-pub fn parse_integer(token: &str) -> Result<i64, TycoError> {
-    let trimmed = token.trim();
-    let negative = trimmed.starts_with('-');
-    let body = if negative { &trimmed[1..] } else { trimmed };
-
-    let value = if body.starts_with("0x") || body.starts_with("0X") {
-        i64::from_str_radix(&body[2..], 16)
-    } else if body.starts_with("0o") || body.starts_with("0O") {
-        i64::from_str_radix(&body[2..], 8)
-    } else if body.starts_with("0b") || body.starts_with("0B") {
-        i64::from_str_radix(&body[2..], 2)
-    } else {
-        body.parse()
-    }
-    .map_err(|e| TycoError::parse(format!("Failed to parse integer '{token}': {e}")))?;
-
-    Ok(if negative { -value } else { value })
-}
-
 pub fn normalize_time(value: &str) -> String {
     if let Some(idx) = value.find('.') {
         let (head, tail) = value.split_at(idx + 1);
@@ -131,7 +112,7 @@ pub fn normalize_datetime(value: &str) -> String {
     }
     if let Some(idx) = result.find('.') {
         let tz_start = result[idx..]
-            .find(|c: char| c == '+' || c == '-')
+            .find(['+', '-'])
             .map(|offset| idx + offset)
             .unwrap_or(result.len());
         let fractional = normalize_time(&result[idx..tz_start]);
@@ -203,9 +184,92 @@ fn take_hex(
 }
 
 pub fn strip_leading_newline(value: &str) -> Cow<'_, str> {
-    if value.starts_with('\n') {
-        Cow::Owned(value[1..].to_string())
+    if let Some(rest) = value.strip_prefix('\n') {
+        Cow::Owned(rest.to_string())
     } else {
         Cow::Borrowed(value)
     }
 }
+
+pub fn parse_bytes_literal(token: &str) -> Result<Vec<u8>, TycoError> {
+    let trimmed = token.trim();
+    let (encoding, quoted) = if let Some(rest) = trimmed.strip_prefix("b64") {
+        ("b64", rest)
+    } else if let Some(rest) = trimmed.strip_prefix("hex") {
+        ("hex", rest)
+    } else {
+        return Err(TycoError::parse(format!(
+            "Bytes literal must be written as b64\"...\" or hex\"...\": {trimmed}"
+        )));
+    };
+    let payload = ['"', '\'']
+        .iter()
+        .find_map(|quote| quoted.strip_prefix(*quote)?.strip_suffix(*quote))
+        .ok_or_else(|| TycoError::parse(format!("Unterminated bytes literal: {trimmed}")))?;
+
+    if encoding == "hex" {
+        if payload.len() % 2 != 0 {
+            return Err(TycoError::parse(format!(
+                "Hex bytes literal must have an even number of digits, found {}",
+                payload.len()
+            )));
+        }
+        if !payload.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return Err(TycoError::parse(format!(
+                "Invalid hex bytes literal: {trimmed}"
+            )));
+        }
+        Ok((0..payload.len())
+            .step_by(2)
+            .map(|idx| u8::from_str_radix(&payload[idx..idx + 2], 16).unwrap())
+            .collect())
+    } else {
+        if payload.len() % 4 != 0 {
+            return Err(TycoError::parse(format!(
+                "Base64 bytes literal length must be a multiple of 4, found {}",
+                payload.len()
+            )));
+        }
+        BASE64
+            .decode(payload)
+            .map_err(|e| TycoError::parse(format!("Invalid base64 bytes literal: {e}")))
+    }
+}
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    BASE64.encode(bytes)
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Matches a file name against a pattern where `*` matches any run of
+/// characters and `?` matches exactly one.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
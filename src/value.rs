@@ -1,14 +1,26 @@
 use indexmap::IndexMap;
 use serde_json::Value as JsonValue;
-use std::{borrow::Cow, collections::VecDeque};
-
-use crate::{context::TycoContext, utils::unescape_basic_string};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    ops::Index,
+};
+
+use crate::{
+    context::{PrimaryKey, TycoContext, ValueOrigin},
+    error::TycoError,
+    numeric::format_float,
+    utils::{encode_base64, encode_hex, unescape_basic_string},
+};
 
 #[derive(Clone, Debug)]
 pub struct TycoString {
     pub value: String,
     pub has_template: bool,
     pub is_literal: bool,
+    /// The unrendered text, kept once the string has been rendered so that
+    /// `TycoContext::render` can run again after the context changes.
+    template: Option<String>,
 }
 
 impl TycoString {
@@ -17,10 +29,34 @@ impl TycoString {
             value,
             has_template,
             is_literal,
+            template: None,
+        }
+    }
+
+    /// The template this string was rendered from, if it had placeholders.
+    pub fn template(&self) -> Option<&str> {
+        self.template.as_deref()
+    }
+
+    pub(crate) fn reset_template(&mut self) {
+        if let Some(template) = self.template.take() {
+            self.value = template;
+            self.has_template = true;
         }
     }
 
     pub fn render(&mut self, ctx: &TycoContext, current: Option<&TycoInstance>) {
+        self.render_tracking(ctx, current, &mut HashSet::new());
+    }
+
+    /// Renders like [`TycoString::render`] and records the name of every global
+    /// a placeholder resolved through.
+    pub(crate) fn render_tracking(
+        &mut self,
+        ctx: &TycoContext,
+        current: Option<&TycoInstance>,
+        used_globals: &mut HashSet<String>,
+    ) {
         if !self.has_template || self.is_literal {
             return;
         }
@@ -38,7 +74,7 @@ impl TycoString {
                     placeholder.push(*next);
                     chars.next();
                 }
-                if let Some(resolved) = resolve_placeholder(&placeholder, ctx, current) {
+                if let Some(resolved) = resolve_placeholder(&placeholder, ctx, current, used_globals) {
                     result.push_str(&resolved);
                 } else {
                     result.push('{');
@@ -50,7 +86,11 @@ impl TycoString {
             }
         }
 
-        self.value = unescape_basic_string(&result).unwrap_or(result);
+        let template = std::mem::replace(
+            &mut self.value,
+            unescape_basic_string(&result).unwrap_or(result),
+        );
+        self.template = Some(template);
         self.has_template = false;
     }
 }
@@ -59,8 +99,10 @@ fn resolve_placeholder(
     placeholder: &str,
     ctx: &TycoContext,
     current: Option<&TycoInstance>,
+    used_globals: &mut HashSet<String>,
 ) -> Option<String> {
     fn resolve_from_instance<'a>(
+        ctx: &'a TycoContext,
         instance: &'a TycoInstance,
         parts: &[&'a str],
     ) -> Option<&'a TycoValue> {
@@ -87,7 +129,7 @@ fn resolve_placeholder(
 
                 current_container = match value {
                     TycoValue::Instance(inst) => Some(inst),
-                    TycoValue::Reference(reference) => reference.resolved.as_deref(),
+                    TycoValue::Reference(reference) => ctx.resolve(reference),
                     _ => return None,
                 };
             } else if queue.len() > 1 {
@@ -106,10 +148,11 @@ fn resolve_placeholder(
         current_value
     }
 
+    /// Returns the resolved value together with the global it was reached from.
     fn resolve_from_globals<'a>(
         ctx: &'a TycoContext,
         parts: &[&'a str],
-    ) -> Option<&'a TycoValue> {
+    ) -> Option<(&'a str, &'a TycoValue)> {
         if parts.is_empty() {
             return None;
         }
@@ -117,27 +160,32 @@ fn resolve_placeholder(
         let mut queue: VecDeque<Cow<'_, str>> =
             parts.iter().map(|part| Cow::Borrowed(*part)).collect();
         let mut current_container: Option<&TycoInstance> = None;
-        let mut current_value: Option<&TycoValue> = None;
+        let mut root: Option<&'a str> = None;
 
         while !queue.is_empty() {
             let attr_name = queue.front().cloned().unwrap();
 
-            if let Some(container) = current_container {
-                current_value = container.get_attribute(attr_name.as_ref());
+            let current_value = if let Some(container) = current_container {
+                container.get_attribute(attr_name.as_ref())
             } else {
-                current_value = ctx.globals().get(attr_name.as_ref());
-            }
+                ctx.globals()
+                    .get_key_value(attr_name.as_ref())
+                    .map(|(name, value)| {
+                        root = Some(name.as_str());
+                        value
+                    })
+            };
 
             if let Some(value) = current_value {
                 queue.pop_front();
 
                 if queue.is_empty() {
-                    return Some(value);
+                    return root.map(|name| (name, value));
                 }
 
                 current_container = match value {
                     TycoValue::Instance(inst) => Some(inst),
-                    TycoValue::Reference(reference) => reference.resolved.as_deref(),
+                    TycoValue::Reference(reference) => ctx.resolve(reference),
                     _ => return None,
                 };
             } else if queue.len() > 1 {
@@ -153,7 +201,7 @@ fn resolve_placeholder(
             }
         }
 
-        current_value
+        None
     }
 
     let path_parts: Vec<&str> = placeholder.split('.').collect();
@@ -161,14 +209,20 @@ fn resolve_placeholder(
         return None;
     }
 
-    let mut value = current.and_then(|instance| resolve_from_instance(instance, &path_parts));
+    let mut value = current.and_then(|instance| resolve_from_instance(ctx, instance, &path_parts));
 
     if value.is_none() && path_parts.len() > 1 && path_parts[0] == "global" {
-        value = resolve_from_globals(ctx, &path_parts[1..]);
+        value = resolve_from_globals(ctx, &path_parts[1..]).map(|(name, value)| {
+            used_globals.insert(name.to_string());
+            value
+        });
     }
 
     if value.is_none() {
-        value = resolve_from_globals(ctx, &path_parts);
+        value = resolve_from_globals(ctx, &path_parts).map(|(name, value)| {
+            used_globals.insert(name.to_string());
+            value
+        });
     }
 
     value.map(TycoValue::to_template_text)
@@ -179,6 +233,13 @@ pub struct TycoInstance {
     struct_name: String,
     fields: IndexMap<String, TycoValue>,
     field_order: Vec<String>,
+    /// Fields filled in from the struct's schema defaults while rendering.
+    defaulted: HashSet<String>,
+    /// Fields converted to their declared type after their template was
+    /// rendered, with the unrendered value to restore before rendering again.
+    rendered: HashMap<String, TycoValue>,
+    /// Where each field written on the instance came from.
+    origins: HashMap<String, ValueOrigin>,
 }
 
 impl TycoInstance {
@@ -187,6 +248,9 @@ impl TycoInstance {
             struct_name: name.into(),
             fields: IndexMap::new(),
             field_order: Vec::new(),
+            defaulted: HashSet::new(),
+            rendered: HashMap::new(),
+            origins: HashMap::new(),
         }
     }
 
@@ -194,24 +258,97 @@ impl TycoInstance {
         &self.struct_name
     }
 
+    pub(crate) fn set_struct_name(&mut self, name: String) {
+        self.struct_name = name;
+    }
+
     pub fn set_attribute(&mut self, name: impl Into<String>, value: TycoValue) {
         let name = name.into();
         if !self.fields.contains_key(&name) {
             self.field_order.push(name.clone());
         }
+        self.defaulted.remove(&name);
+        self.rendered.remove(&name);
         self.fields.insert(name, value);
     }
 
+    /// Replaces a rendered template with `value`, keeping the template so
+    /// `reset_rendering` can put it back.
+    pub(crate) fn set_rendered_attribute(&mut self, name: &str, value: TycoValue) {
+        if let Some(template) = self.fields.insert(name.to_string(), value) {
+            self.rendered.insert(name.to_string(), template);
+        }
+    }
+
+    pub(crate) fn origin(&self, name: &str) -> Option<&ValueOrigin> {
+        self.origins.get(name)
+    }
+
+    pub(crate) fn set_origin(&mut self, name: String, origin: ValueOrigin) {
+        self.origins.insert(name, origin);
+    }
+
+    pub(crate) fn set_default_attribute(&mut self, name: String, value: TycoValue) {
+        self.set_attribute(name.clone(), value);
+        self.defaulted.insert(name);
+    }
+
+    /// Whether the field was not written on the instance and took the struct's
+    /// default instead.
+    pub fn is_default(&self, name: &str) -> bool {
+        self.defaulted.contains(name)
+    }
+
+    /// Undoes rendering: restores templates and drops fields filled in from
+    /// schema defaults, so the next render picks up current defaults.
+    pub(crate) fn reset_rendering(&mut self) {
+        for name in std::mem::take(&mut self.defaulted) {
+            self.remove_attribute(&name);
+        }
+        for (name, template) in std::mem::take(&mut self.rendered) {
+            if let Some(value) = self.fields.get_mut(&name) {
+                *value = template;
+            }
+        }
+        for value in self.fields.values_mut() {
+            value.reset_rendering();
+        }
+    }
+
     pub fn get_attribute(&self, name: &str) -> Option<&TycoValue> {
         self.fields.get(name)
     }
 
+    /// The field converted with `TryFrom<&TycoValue>`, e.g.
+    /// `host.get_as::<u16>("port")` or `host.get_as::<Option<&str>>("alias")`.
+    /// Missing fields read as null. On mismatch the error names the field.
+    pub fn get_as<'a, T>(&'a self, name: &str) -> Result<T, TycoError>
+    where
+        T: TryFrom<&'a TycoValue, Error = TycoError>,
+    {
+        let value = self.get_attribute(name).unwrap_or(&TycoValue::Null);
+        T::try_from(value).map_err(|err| match err {
+            TycoError::TypeMismatch {
+                field: None,
+                expected,
+                found,
+            } => TycoError::TypeMismatch {
+                field: Some(format!("{}.{name}", self.struct_name)),
+                expected,
+                found,
+            },
+            other => other,
+        })
+    }
+
     pub fn get_attribute_mut(&mut self, name: &str) -> Option<&mut TycoValue> {
         self.fields.get_mut(name)
     }
 
     pub fn remove_attribute(&mut self, name: &str) -> Option<TycoValue> {
         self.field_order.retain(|field| field != name);
+        self.defaulted.remove(name);
+        self.rendered.remove(name);
         self.fields.shift_remove(name)
     }
 
@@ -232,6 +369,15 @@ impl TycoInstance {
             if !replaced {
                 self.field_order.push(to.to_string());
             }
+            if self.defaulted.remove(from) {
+                self.defaulted.insert(to.to_string());
+            }
+            if let Some(template) = self.rendered.remove(from) {
+                self.rendered.insert(to.to_string(), template);
+            }
+            if let Some(origin) = self.origins.remove(from) {
+                self.origins.insert(to.to_string(), origin);
+            }
             self.fields.insert(to.to_string(), value);
         }
     }
@@ -248,6 +394,14 @@ impl TycoInstance {
         &self.field_order
     }
 
+    pub fn to_json_value(&self, ctx: &TycoContext) -> JsonValue {
+        self.to_json_value_with(ctx, &JsonOptions::default())
+    }
+
+    pub fn to_json_value_with(&self, ctx: &TycoContext, options: &JsonOptions) -> JsonValue {
+        JsonEncoder::new(ctx, options).encode_instance(self)
+    }
+
     pub fn enforce_order_from_schema(&mut self, schema: &[crate::context::FieldSchema]) {
         let mut ordered = Vec::new();
         for field in schema {
@@ -264,15 +418,32 @@ impl TycoInstance {
     }
 }
 
+/// `instance["cores"]`; panics when the field is not set, like `IndexMap`.
+impl Index<&str> for TycoInstance {
+    type Output = TycoValue;
+
+    fn index(&self, name: &str) -> &TycoValue {
+        match self.get_attribute(name) {
+            Some(value) => value,
+            None => panic!("{} has no field '{name}'", self.struct_name),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TycoReference {
     pub struct_name: String,
-    pub primary_key: String,
-    pub resolved: Option<Box<TycoInstance>>,
+    pub primary_key: PrimaryKey,
+    /// Position of the target in its struct's `instances()`, filled in by
+    /// `TycoContext::render`. Every reference to the same instance shares it;
+    /// use `TycoContext::resolve` to follow the reference, which checks the
+    /// position against `primary_key` and looks the target up again when
+    /// instances have moved since.
+    pub resolved: Option<usize>,
 }
 
 impl TycoReference {
-    pub fn new(struct_name: impl Into<String>, primary_key: impl Into<String>) -> Self {
+    pub fn new(struct_name: impl Into<String>, primary_key: impl Into<PrimaryKey>) -> Self {
         Self {
             struct_name: struct_name.into(),
             primary_key: primary_key.into(),
@@ -281,42 +452,390 @@ impl TycoReference {
     }
 }
 
+/// How `bytes` values are written by `to_json_with`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BytesEncoding {
+    /// Standard base64 with padding.
+    #[default]
+    Base64,
+    /// Lowercase hexadecimal digits.
+    Hex,
+    /// An array of byte values.
+    Array,
+}
+
+/// Options controlling JSON output for values that have no native JSON type.
+#[derive(Clone, Debug, Default)]
+pub struct JsonOptions {
+    pub bytes: BytesEncoding,
+}
+
 #[derive(Clone, Debug)]
 pub enum TycoValue {
     Null,
     Bool(bool),
     Int(i64),
+    UInt(u64),
+    #[cfg(feature = "bigint")]
+    BigInt(num_bigint::BigInt),
     Float(f64),
+    Decimal(String),
     String(TycoString),
     Date(String),
     Time(String),
     DateTime(String),
+    Bytes(Vec<u8>),
     Array(Vec<TycoValue>),
     Instance(TycoInstance),
     Reference(TycoReference),
 }
 
+impl From<&str> for TycoValue {
+    fn from(value: &str) -> Self {
+        TycoValue::String(TycoString::new(value.to_string(), false, true))
+    }
+}
+
+impl From<String> for TycoValue {
+    fn from(value: String) -> Self {
+        TycoValue::String(TycoString::new(value, false, true))
+    }
+}
+
+impl From<TycoString> for TycoValue {
+    fn from(value: TycoString) -> Self {
+        TycoValue::String(value)
+    }
+}
+
+impl From<bool> for TycoValue {
+    fn from(value: bool) -> Self {
+        TycoValue::Bool(value)
+    }
+}
+
+impl From<i32> for TycoValue {
+    fn from(value: i32) -> Self {
+        TycoValue::Int(value.into())
+    }
+}
+
+impl From<i64> for TycoValue {
+    fn from(value: i64) -> Self {
+        TycoValue::Int(value)
+    }
+}
+
+impl From<u64> for TycoValue {
+    fn from(value: u64) -> Self {
+        TycoValue::UInt(value)
+    }
+}
+
+impl From<f64> for TycoValue {
+    fn from(value: f64) -> Self {
+        TycoValue::Float(value)
+    }
+}
+
+impl From<TycoInstance> for TycoValue {
+    fn from(value: TycoInstance) -> Self {
+        TycoValue::Instance(value)
+    }
+}
+
+impl From<TycoReference> for TycoValue {
+    fn from(value: TycoReference) -> Self {
+        TycoValue::Reference(value)
+    }
+}
+
+impl<T: Into<TycoValue>> From<Vec<T>> for TycoValue {
+    fn from(items: Vec<T>) -> Self {
+        TycoValue::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// `None` becomes null.
+impl<T: Into<TycoValue>> From<Option<T>> for TycoValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(TycoValue::Null, Into::into)
+    }
+}
+
+fn convert<'a, T>(
+    value: &'a TycoValue,
+    expected: &str,
+    accessor: impl FnOnce(&'a TycoValue) -> Option<T>,
+) -> Result<T, TycoError> {
+    accessor(value).ok_or_else(|| TycoError::type_mismatch(expected, value.type_name()))
+}
+
+impl TryFrom<&TycoValue> for bool {
+    type Error = TycoError;
+
+    fn try_from(value: &TycoValue) -> Result<Self, TycoError> {
+        convert(value, "bool", TycoValue::as_bool)
+    }
+}
+
+impl TryFrom<&TycoValue> for i64 {
+    type Error = TycoError;
+
+    fn try_from(value: &TycoValue) -> Result<Self, TycoError> {
+        convert(value, "int", TycoValue::as_i64)
+    }
+}
+
+impl TryFrom<&TycoValue> for u64 {
+    type Error = TycoError;
+
+    fn try_from(value: &TycoValue) -> Result<Self, TycoError> {
+        convert(value, "uint", TycoValue::as_u64)
+    }
+}
+
+/// Narrower integers, for fields such as ports (`u16`) or counts (`usize`).
+macro_rules! impl_try_from_for_integers {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<&TycoValue> for $ty {
+            type Error = TycoError;
+
+            fn try_from(value: &TycoValue) -> Result<Self, TycoError> {
+                convert(value, stringify!($ty), |value| match value {
+                    TycoValue::Int(v) => <$ty>::try_from(*v).ok(),
+                    TycoValue::UInt(v) => <$ty>::try_from(*v).ok(),
+                    _ => None,
+                })
+            }
+        }
+    )*};
+}
+
+impl_try_from_for_integers!(i8, i16, i32, isize, u8, u16, u32, usize);
+
+impl TryFrom<&TycoValue> for f64 {
+    type Error = TycoError;
+
+    fn try_from(value: &TycoValue) -> Result<Self, TycoError> {
+        convert(value, "float", TycoValue::as_f64)
+    }
+}
+
+impl<'a> TryFrom<&'a TycoValue> for &'a str {
+    type Error = TycoError;
+
+    fn try_from(value: &'a TycoValue) -> Result<Self, TycoError> {
+        convert(value, "str", TycoValue::as_str)
+    }
+}
+
+impl TryFrom<&TycoValue> for String {
+    type Error = TycoError;
+
+    fn try_from(value: &TycoValue) -> Result<Self, TycoError> {
+        convert(value, "str", |value| value.as_str().map(str::to_string))
+    }
+}
+
+impl<'a> TryFrom<&'a TycoValue> for &'a [u8] {
+    type Error = TycoError;
+
+    fn try_from(value: &'a TycoValue) -> Result<Self, TycoError> {
+        convert(value, "bytes", TycoValue::as_bytes)
+    }
+}
+
+impl<'a> TryFrom<&'a TycoValue> for &'a TycoInstance {
+    type Error = TycoError;
+
+    fn try_from(value: &'a TycoValue) -> Result<Self, TycoError> {
+        convert(value, "instance", TycoValue::as_instance)
+    }
+}
+
+/// Arrays, converting every item.
+impl<'a, T> TryFrom<&'a TycoValue> for Vec<T>
+where
+    T: TryFrom<&'a TycoValue, Error = TycoError>,
+{
+    type Error = TycoError;
+
+    fn try_from(value: &'a TycoValue) -> Result<Self, TycoError> {
+        convert(value, "array", TycoValue::as_array)?
+            .iter()
+            .map(T::try_from)
+            .collect()
+    }
+}
+
+/// Nullable values: null converts to `None`.
+impl<'a, T> TryFrom<&'a TycoValue> for Option<T>
+where
+    T: TryFrom<&'a TycoValue, Error = TycoError>,
+{
+    type Error = TycoError;
+
+    fn try_from(value: &'a TycoValue) -> Result<Self, TycoError> {
+        match value {
+            TycoValue::Null => Ok(None),
+            value => T::try_from(value).map(Some),
+        }
+    }
+}
+
 impl TycoValue {
+    /// The Tyco type of the value (`int`, `str`, `Host`, ...), as used in
+    /// type mismatch errors.
+    pub fn type_name(&self) -> &str {
+        match self {
+            TycoValue::Null => "null",
+            TycoValue::Bool(_) => "bool",
+            TycoValue::Int(_) => "int",
+            TycoValue::UInt(_) => "uint",
+            #[cfg(feature = "bigint")]
+            TycoValue::BigInt(_) => "bigint",
+            TycoValue::Float(_) => "float",
+            TycoValue::Decimal(_) => "decimal",
+            TycoValue::String(_) => "str",
+            TycoValue::Date(_) => "date",
+            TycoValue::Time(_) => "time",
+            TycoValue::DateTime(_) => "datetime",
+            TycoValue::Bytes(_) => "bytes",
+            TycoValue::Array(_) => "array",
+            TycoValue::Instance(instance) => instance.struct_name(),
+            TycoValue::Reference(reference) => &reference.struct_name,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, TycoValue::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            TycoValue::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// `int` values, and `uint` values that fit.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            TycoValue::Int(v) => Some(*v),
+            TycoValue::UInt(v) => i64::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+
+    /// `uint` values, and non-negative `int` values.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            TycoValue::UInt(v) => Some(*v),
+            TycoValue::Int(v) => u64::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+
+    /// `float` values, and integers converted to floating point.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            TycoValue::Float(v) => Some(*v),
+            TycoValue::Int(v) => Some(*v as f64),
+            TycoValue::UInt(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    /// The text of `str` values, and of `decimal`, `date`, `time` and
+    /// `datetime` values, which are kept as text.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            TycoValue::String(s) => Some(&s.value),
+            TycoValue::Decimal(v)
+            | TycoValue::Date(v)
+            | TycoValue::Time(v)
+            | TycoValue::DateTime(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            TycoValue::Bytes(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[TycoValue]> {
+        match self {
+            TycoValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Inline instances; use `TycoContext::resolve` for references.
+    pub fn as_instance(&self) -> Option<&TycoInstance> {
+        match self {
+            TycoValue::Instance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+
+    pub fn as_reference(&self) -> Option<&TycoReference> {
+        match self {
+            TycoValue::Reference(reference) => Some(reference),
+            _ => None,
+        }
+    }
+
     pub fn to_template_text(&self) -> String {
         match self {
             TycoValue::Null => "null".to_string(),
             TycoValue::Bool(v) => v.to_string(),
             TycoValue::Int(v) => v.to_string(),
-            TycoValue::Float(v) => v.to_string(),
+            TycoValue::UInt(v) => v.to_string(),
+            #[cfg(feature = "bigint")]
+            TycoValue::BigInt(v) => v.to_string(),
+            TycoValue::Float(v) => format_float(*v),
+            TycoValue::Decimal(v) => v.clone(),
             TycoValue::String(s) => s.value.clone(),
             TycoValue::Date(v) | TycoValue::Time(v) | TycoValue::DateTime(v) => v.clone(),
+            TycoValue::Bytes(v) => encode_base64(v),
             TycoValue::Array(_) => "[array]".to_string(),
             TycoValue::Instance(_) => "[instance]".to_string(),
-            TycoValue::Reference(reference) => reference.primary_key.clone(),
+            TycoValue::Reference(reference) => reference.primary_key.to_string(),
         }
     }
 
     pub fn render_templates(&mut self, ctx: &TycoContext, current: Option<&TycoInstance>) {
+        self.render_templates_tracking(ctx, current, &mut HashSet::new());
+    }
+
+    pub(crate) fn reset_rendering(&mut self) {
+        match self {
+            TycoValue::String(s) => s.reset_template(),
+            TycoValue::Array(items) => {
+                for item in items {
+                    item.reset_rendering();
+                }
+            }
+            TycoValue::Instance(instance) => instance.reset_rendering(),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn render_templates_tracking(
+        &mut self,
+        ctx: &TycoContext,
+        current: Option<&TycoInstance>,
+        used_globals: &mut HashSet<String>,
+    ) {
         match self {
-            TycoValue::String(s) => s.render(ctx, current),
+            TycoValue::String(s) => s.render_tracking(ctx, current, used_globals),
             TycoValue::Array(items) => {
                 for item in items {
-                    item.render_templates(ctx, current);
+                    item.render_templates_tracking(ctx, current, used_globals);
                 }
             }
             TycoValue::Instance(instance) => {
@@ -324,7 +843,11 @@ impl TycoValue {
                 let mut snapshot_instance = instance.clone();
                 for key in keys {
                     if let Some(value) = instance.attributes_mut().get_mut(&key) {
-                        value.render_templates(ctx, Some(&snapshot_instance));
+                        value.render_templates_tracking(
+                            ctx,
+                            Some(&snapshot_instance),
+                            used_globals,
+                        );
                     }
                     snapshot_instance = instance.clone();
                 }
@@ -333,33 +856,161 @@ impl TycoValue {
         }
     }
 
+    /// References no longer carry a copy of their target, so without a
+    /// context they are written as their primary key instead of inlined.
+    #[deprecated(
+        note = "references are written as their primary key; use `to_json_value_in` to inline \
+                them or `to_json_value_detached` for the key-only form"
+    )]
     pub fn to_json_value(&self) -> JsonValue {
-        match self {
+        self.to_json_value_detached()
+    }
+
+    #[deprecated(
+        note = "references are written as their primary key; use `to_json_value_in_with` to \
+                inline them or `to_json_value_detached_with` for the key-only form"
+    )]
+    pub fn to_json_value_with(&self, options: &JsonOptions) -> JsonValue {
+        self.to_json_value_detached_with(options)
+    }
+
+    /// Converts the value on its own. References cannot be followed without
+    /// their context and are written as their primary key; use
+    /// `to_json_value_in` to inline the referenced instances.
+    pub fn to_json_value_detached(&self) -> JsonValue {
+        self.to_json_value_detached_with(&JsonOptions::default())
+    }
+
+    pub fn to_json_value_detached_with(&self, options: &JsonOptions) -> JsonValue {
+        JsonEncoder::detached(options).encode_value(self)
+    }
+
+    /// Converts the value, inlining the instances its references point at
+    /// in `ctx`.
+    pub fn to_json_value_in(&self, ctx: &TycoContext) -> JsonValue {
+        self.to_json_value_in_with(ctx, &JsonOptions::default())
+    }
+
+    pub fn to_json_value_in_with(&self, ctx: &TycoContext, options: &JsonOptions) -> JsonValue {
+        JsonEncoder::new(ctx, options).encode_value(self)
+    }
+}
+
+pub(crate) fn struct_instance_json(
+    ctx: &TycoContext,
+    options: &JsonOptions,
+    struct_name: &str,
+    index: usize,
+) -> JsonValue {
+    JsonEncoder::new(ctx, options).encode_struct_instance(struct_name, index)
+}
+
+/// Converts values to JSON, inlining referenced instances. References that
+/// lead back to an instance already being expanded are written as `null`;
+/// without a context, references are written as their primary key.
+struct JsonEncoder<'a> {
+    ctx: Option<&'a TycoContext>,
+    options: &'a JsonOptions,
+    expanding: Vec<(&'a str, usize)>,
+}
+
+impl<'a> JsonEncoder<'a> {
+    fn new(ctx: &'a TycoContext, options: &'a JsonOptions) -> Self {
+        Self {
+            ctx: Some(ctx),
+            options,
+            expanding: Vec::new(),
+        }
+    }
+
+    fn detached(options: &'a JsonOptions) -> Self {
+        Self {
+            ctx: None,
+            options,
+            expanding: Vec::new(),
+        }
+    }
+
+    fn encode_value(&mut self, value: &'a TycoValue) -> JsonValue {
+        match value {
             TycoValue::Null => JsonValue::Null,
             TycoValue::Bool(v) => JsonValue::Bool(*v),
             TycoValue::Int(v) => JsonValue::from(*v),
-            TycoValue::Float(v) => JsonValue::from(*v),
+            TycoValue::UInt(v) => JsonValue::from(*v),
+            #[cfg(feature = "bigint")]
+            TycoValue::BigInt(v) => exact_number_json(&v.to_string()),
+            TycoValue::Float(v) if v.is_finite() => JsonValue::from(*v),
+            TycoValue::Float(v) => JsonValue::from(format_float(*v)),
+            TycoValue::Decimal(v) => exact_number_json(v),
             TycoValue::String(s) => JsonValue::from(s.value.clone()),
             TycoValue::Date(v) | TycoValue::Time(v) | TycoValue::DateTime(v) => {
                 JsonValue::from(v.clone())
             }
+            TycoValue::Bytes(v) => match self.options.bytes {
+                BytesEncoding::Base64 => JsonValue::from(encode_base64(v)),
+                BytesEncoding::Hex => JsonValue::from(encode_hex(v)),
+                BytesEncoding::Array => JsonValue::from(v.clone()),
+            },
             TycoValue::Array(items) => {
-                JsonValue::Array(items.iter().map(|value| value.to_json_value()).collect())
+                JsonValue::Array(items.iter().map(|value| self.encode_value(value)).collect())
             }
-            TycoValue::Instance(instance) => {
-                let mut map = serde_json::Map::new();
-                for key in instance.field_order() {
-                    if let Some(value) = instance.get_attribute(key) {
-                        map.insert(key.clone(), value.to_json_value());
-                    }
+            TycoValue::Instance(instance) => self.encode_instance(instance),
+            TycoValue::Reference(reference) => {
+                let Some(ctx) = self.ctx else {
+                    return match reference.primary_key.parts() {
+                        [part] => self.encode_value(part),
+                        parts => JsonValue::Array(
+                            parts.iter().map(|part| self.encode_value(part)).collect(),
+                        ),
+                    };
+                };
+                let Some(index) = ctx.resolve_position(reference) else {
+                    return JsonValue::Null;
+                };
+                if self.expanding.contains(&(reference.struct_name.as_str(), index)) {
+                    return JsonValue::Null;
                 }
-                JsonValue::Object(map)
+                self.encode_struct_instance(&reference.struct_name, index)
+            }
+        }
+    }
+
+    fn encode_struct_instance(&mut self, struct_name: &'a str, index: usize) -> JsonValue {
+        let Some(instance) = self
+            .ctx
+            .and_then(|ctx| ctx.get_struct(struct_name))
+            .and_then(|def| def.instances().get(index))
+        else {
+            return JsonValue::Null;
+        };
+        self.expanding.push((struct_name, index));
+        let json = self.encode_instance(instance);
+        self.expanding.pop();
+        json
+    }
+
+    fn encode_instance(&mut self, instance: &'a TycoInstance) -> JsonValue {
+        let mut map = serde_json::Map::new();
+        for key in instance.field_order() {
+            if let Some(value) = instance.get_attribute(key) {
+                map.insert(key.clone(), self.encode_value(value));
             }
-            TycoValue::Reference(reference) => reference
-                .resolved
-                .as_ref()
-                .map(|instance| TycoValue::Instance((**instance).clone()).to_json_value())
-                .unwrap_or(JsonValue::Null),
         }
+        JsonValue::Object(map)
     }
 }
+
+/// Decimal and big-integer literals are emitted as exact JSON numbers when
+/// serde_json keeps arbitrary precision, and as strings otherwise so that the
+/// literal never passes through `f64`.
+#[cfg(feature = "arbitrary-precision")]
+fn exact_number_json(literal: &str) -> JsonValue {
+    serde_json::from_str::<serde_json::Number>(literal.trim_start_matches('+'))
+        .map(JsonValue::Number)
+        .unwrap_or_else(|_| JsonValue::from(literal))
+}
+
+#[cfg(not(feature = "arbitrary-precision"))]
+fn exact_number_json(literal: &str) -> JsonValue {
+    JsonValue::from(literal)
+}
@@ -0,0 +1,541 @@
+//! Writes a context back out as a `.tyco` document, for tools such as the
+//! merge driver that edit a context and save the result.
+
+use std::ops::Range;
+
+use indexmap::IndexMap;
+
+use crate::{
+    builder::infer_type,
+    context::{FieldSchema, TycoContext, TycoStruct},
+    diff::match_instances,
+    error::TycoError,
+    numeric::format_float,
+    parser::{field_type_name, is_scalar_type, DEFAULT_UPDATE_RE, FIELD_RE, STRUCT_DEF_RE},
+    utils::{encode_base64, has_unclosed_delimiter, strip_inline_comment},
+    value::{TycoInstance, TycoString, TycoValue},
+};
+
+pub(crate) fn write_context(context: &TycoContext) -> Result<String, TycoError> {
+    if let Some(name) = context.structs().keys().find(|name| name.contains('.')) {
+        return Err(TycoError::Schema(format!(
+            "Cannot write '{name}': structs from imported namespaces belong to another file"
+        )));
+    }
+    // Write templates and explicitly set fields, not their rendered values.
+    let mut context = context.clone();
+    context.reset_rendering();
+
+    let mut out = String::new();
+    let (scalar_globals, struct_globals): (Vec<_>, Vec<_>) = context
+        .globals()
+        .iter()
+        .partition(|(name, value)| is_scalar_global(&context, name, value));
+    for (name, value) in &scalar_globals {
+        out.push_str(&global_line(&context, name, value));
+    }
+    for struct_def in context.structs().values() {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        write_struct(&mut out, struct_def);
+    }
+    // Globals holding instances come last so that inline instances of
+    // structs without a primary key parse against their struct.
+    if !struct_globals.is_empty() && !out.is_empty() {
+        out.push('\n');
+    }
+    for (name, value) in &struct_globals {
+        out.push_str(&global_line(&context, name, value));
+    }
+    Ok(out)
+}
+
+/// Rewrites `source`, the standalone file `original` was parsed from, to
+/// declare `updated` instead. Only the lines of globals, fields and instances
+/// that differ between the two contexts are replaced, so comments and the
+/// layout of everything else are kept; new declarations are added next to
+/// their neighbours and new structs at the end.
+pub(crate) fn rewrite_source(
+    source: &str,
+    original: &TycoContext,
+    updated: &TycoContext,
+) -> Result<String, TycoError> {
+    if let Some(name) = updated.structs().keys().find(|name| name.contains('.')) {
+        return Err(TycoError::Schema(format!(
+            "Cannot write '{name}': structs from imported namespaces belong to another file"
+        )));
+    }
+    let mut original = original.clone();
+    original.reset_rendering();
+    let mut updated = updated.clone();
+    updated.reset_rendering();
+
+    let lines = source.lines().collect::<Vec<_>>();
+    let layout = SourceLayout::scan(&lines)?;
+    let mut edits = SourceEdits::new(&lines);
+
+    for (name, value) in original.globals() {
+        let range = layout.globals.get(name).ok_or_else(|| unmatched(name))?;
+        match updated.globals().get(name) {
+            None => edits.delete(range),
+            Some(new) => {
+                let line = global_line(&updated, name, new);
+                if line != global_line(&original, name, value) {
+                    edits.replace(range, line.trim_end());
+                }
+            }
+        }
+    }
+    let global_anchor = layout
+        .globals
+        .values()
+        .map(|range| range.end)
+        .max()
+        .unwrap_or(0);
+    let mut trailing_globals = Vec::new();
+    for (name, value) in updated.globals() {
+        if original.globals().contains_key(name) {
+            continue;
+        }
+        let line = global_line(&updated, name, value);
+        if is_scalar_global(&updated, name, value) {
+            edits.insert(global_anchor, line.trim_end());
+        } else {
+            trailing_globals.push(line);
+        }
+    }
+
+    for (name, old) in original.structs() {
+        let block = layout.structs.get(name).ok_or_else(|| unmatched(name))?;
+        let Some(new) = updated.get_struct(name) else {
+            edits.delete(&(block.header..block.end));
+            continue;
+        };
+        if block.instances.len() != old.instances().len() {
+            return Err(unmatched(name));
+        }
+        for field in old.fields() {
+            let range = block
+                .fields
+                .get(&field.name)
+                .ok_or_else(|| unmatched(name))?;
+            let kept = new.fields().iter().find(|f| f.name == field.name);
+            if kept.is_some_and(|kept| field_line(kept) == field_line(field)) {
+                continue;
+            }
+            // The declaration is written with its current default, which
+            // replaces any later `  field: value` updates.
+            match kept {
+                Some(kept) => edits.replace(range, field_line(kept).trim_end()),
+                None => edits.delete(range),
+            }
+            for (_, range) in block.defaults.iter().filter(|(of, _)| *of == field.name) {
+                edits.delete(range);
+            }
+        }
+        let field_anchor = block
+            .instances
+            .first()
+            .map_or(block.end, |range| range.start);
+        for field in new.fields() {
+            if !old.fields().iter().any(|f| f.name == field.name) {
+                edits.insert(field_anchor, field_line(field).trim_end());
+            }
+        }
+
+        let matched = match_instances(old, new);
+        let mut anchor = field_anchor;
+        for (old_idx, new_idx) in &matched {
+            let row = instance_row(new, &new.instances()[*new_idx]);
+            match old_idx {
+                Some(old_idx) => {
+                    let range = &block.instances[*old_idx];
+                    if row != instance_row(old, &old.instances()[*old_idx]) {
+                        let indent = leading_whitespace(lines[range.start]);
+                        edits.replace(range, &format!("{indent}- {row}"));
+                    }
+                    anchor = range.end;
+                }
+                None => {
+                    let indent = block
+                        .instances
+                        .first()
+                        .map_or("  ", |range| leading_whitespace(lines[range.start]));
+                    edits.insert(anchor, &format!("{indent}- {row}"));
+                }
+            }
+        }
+        for (idx, range) in block.instances.iter().enumerate() {
+            if !matched.iter().any(|(old_idx, _)| *old_idx == Some(idx)) {
+                edits.delete(range);
+            }
+        }
+    }
+
+    let mut trailing = String::new();
+    for struct_def in updated.structs().values() {
+        if original.get_struct(struct_def.name()).is_none() {
+            trailing.push('\n');
+            write_struct(&mut trailing, struct_def);
+        }
+    }
+    if !trailing_globals.is_empty() {
+        trailing.push('\n');
+        trailing.extend(trailing_globals);
+    }
+    Ok(edits.finish(&trailing))
+}
+
+fn unmatched(name: &str) -> TycoError {
+    TycoError::Schema(format!(
+        "Cannot match '{name}' to the lines of the source it was parsed from"
+    ))
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Where each global, field and instance of a source is declared, as ranges
+/// of line indices. Lines are classified the way `TycoParser::parse_lines`
+/// reads them. Sources that declare a struct in more than one block, or
+/// repeat a global or field, are rejected since their lines cannot be
+/// matched to a context unambiguously.
+#[derive(Default)]
+struct SourceLayout {
+    globals: IndexMap<String, Range<usize>>,
+    structs: IndexMap<String, BlockLayout>,
+}
+
+struct BlockLayout {
+    header: usize,
+    fields: IndexMap<String, Range<usize>>,
+    /// `  field: value` lines updating a default.
+    defaults: Vec<(String, Range<usize>)>,
+    instances: Vec<Range<usize>>,
+    /// One past the last line declaring anything in the block.
+    end: usize,
+}
+
+impl SourceLayout {
+    fn scan(lines: &[&str]) -> Result<Self, TycoError> {
+        let mut layout = SourceLayout::default();
+        let mut current: Option<String> = None;
+        // Set by a global declared inside a struct block: the parser still
+        // reads the lines after it into the block, which then cannot be
+        // removed as one range.
+        let mut interrupted = false;
+        let mut in_instances = false;
+        let mut idx = 0;
+        while idx < lines.len() {
+            let line = lines[idx];
+            let start = idx;
+            let trimmed = strip_inline_comment(line);
+            let trimmed_ws = trimmed.trim();
+            if trimmed_ws.is_empty() {
+                idx += 1;
+                continue;
+            }
+
+            if let Some(caps) = STRUCT_DEF_RE.captures(trimmed_ws) {
+                let name = caps[1].to_string();
+                if layout.structs.contains_key(&name) {
+                    return Err(TycoError::Schema(format!(
+                        "Cannot rewrite '{name}': the struct is declared in more than one block"
+                    )));
+                }
+                layout.structs.insert(
+                    name.clone(),
+                    BlockLayout {
+                        header: idx,
+                        fields: IndexMap::new(),
+                        defaults: Vec::new(),
+                        instances: Vec::new(),
+                        end: idx + 1,
+                    },
+                );
+                current = Some(name);
+                interrupted = false;
+                in_instances = false;
+            } else if let Some(caps) = FIELD_RE.captures(line) {
+                idx = multiline_end(idx, lines, caps.get(5).map_or("", |m| m.as_str()));
+                let name = caps[4].to_string();
+                in_instances = false;
+                if line.starts_with(|c: char| !c.is_whitespace()) {
+                    if layout
+                        .globals
+                        .insert(name.clone(), start..idx + 1)
+                        .is_some()
+                    {
+                        return Err(TycoError::Schema(format!(
+                            "Cannot rewrite '{name}': the global is declared more than once"
+                        )));
+                    }
+                    interrupted |= current.is_some();
+                } else {
+                    let block = layout.block(&current, interrupted)?;
+                    if block.fields.insert(name.clone(), start..idx + 1).is_some() {
+                        return Err(TycoError::Schema(format!(
+                            "Cannot rewrite '{name}': the field is declared more than once"
+                        )));
+                    }
+                    block.end = idx + 1;
+                }
+            } else if let (Some(caps), Some(_)) = (DEFAULT_UPDATE_RE.captures(line), &current) {
+                idx = multiline_end(idx, lines, caps.get(2).map_or("", |m| m.as_str()));
+                let block = layout.block(&current, interrupted)?;
+                block.defaults.push((caps[1].to_string(), start..idx + 1));
+                block.end = idx + 1;
+            } else if trimmed_ws.starts_with('-') {
+                let mut row = trimmed_ws.trim_start_matches('-').trim().to_string();
+                while row.ends_with('\\') && idx + 1 < lines.len() {
+                    row.pop();
+                    idx += 1;
+                    row.push(' ');
+                    row.push_str(strip_inline_comment(lines[idx]).trim());
+                }
+                idx = multiline_end(idx, lines, &row);
+                let block = layout.block(&current, interrupted)?;
+                block.instances.push(start..idx + 1);
+                block.end = idx + 1;
+                in_instances = true;
+            } else if in_instances && line.starts_with(char::is_whitespace) {
+                let block = layout.block(&current, interrupted)?;
+                if let Some(last) = block.instances.last_mut() {
+                    last.end = idx + 1;
+                }
+                block.end = idx + 1;
+            }
+            idx += 1;
+        }
+        Ok(layout)
+    }
+
+    fn block(
+        &mut self,
+        current: &Option<String>,
+        interrupted: bool,
+    ) -> Result<&mut BlockLayout, TycoError> {
+        let Some(name) = current else {
+            return Err(TycoError::parse(
+                "Instance data encountered outside of a struct block",
+            ));
+        };
+        if interrupted {
+            return Err(TycoError::Schema(format!(
+                "Cannot rewrite '{name}': a global is declared inside the struct block"
+            )));
+        }
+        Ok(self
+            .structs
+            .get_mut(name)
+            .expect("the current struct has a layout"))
+    }
+}
+
+/// The index of the last line of a value starting on line `idx`, following
+/// `TycoParser::accumulate_multiline` for multi-line strings.
+fn multiline_end(idx: usize, lines: &[&str], value: &str) -> usize {
+    let (double, single) = ("\"\"\"", "'''");
+    if !has_unclosed_delimiter(value, double) && !has_unclosed_delimiter(value, single) {
+        return idx;
+    }
+    let delimiter = if value.contains(double) {
+        double
+    } else {
+        single
+    };
+    let mut value = value.to_string();
+    let mut cursor = idx;
+    while cursor + 1 < lines.len() && has_unclosed_delimiter(&value, delimiter) {
+        cursor += 1;
+        value.push('\n');
+        value.push_str(lines[cursor]);
+    }
+    cursor
+}
+
+/// Line edits to a source, applied together by `finish`.
+struct SourceEdits {
+    /// Each original line, its replacement, or `None` once deleted.
+    lines: Vec<Option<String>>,
+    /// Lines inserted before each original line, and after the last.
+    inserted: Vec<Vec<String>>,
+}
+
+impl SourceEdits {
+    fn new(lines: &[&str]) -> Self {
+        SourceEdits {
+            lines: lines.iter().map(|line| Some(line.to_string())).collect(),
+            inserted: vec![Vec::new(); lines.len() + 1],
+        }
+    }
+
+    /// Replaces the lines of a declaration, keeping the comment that ends a
+    /// one-line declaration.
+    fn replace(&mut self, range: &Range<usize>, text: &str) {
+        let mut text = text.to_string();
+        if let (1, Some(line)) = (range.len(), &self.lines[range.start]) {
+            let comment = line[strip_inline_comment(line).len()..].trim();
+            if comment.starts_with('#') {
+                text = format!("{text}  {comment}");
+            }
+        }
+        self.delete(range);
+        self.lines[range.start] = Some(text);
+    }
+
+    fn delete(&mut self, range: &Range<usize>) {
+        self.lines[range.clone()].fill(None);
+    }
+
+    fn insert(&mut self, before: usize, text: &str) {
+        self.inserted[before].push(text.to_string());
+    }
+
+    fn finish(self, trailing: &str) -> String {
+        let mut out = String::new();
+        for (idx, inserted) in self.inserted.iter().enumerate() {
+            for line in inserted
+                .iter()
+                .chain(self.lines.get(idx).into_iter().flatten())
+            {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        out.push_str(trailing);
+        out
+    }
+}
+
+fn global_type(context: &TycoContext, name: &str, value: &TycoValue) -> Option<String> {
+    context
+        .global_type(name)
+        .map(str::to_string)
+        .or_else(|| infer_type(value))
+}
+
+fn is_scalar_global(context: &TycoContext, name: &str, value: &TycoValue) -> bool {
+    global_type(context, name, value).is_none_or(|type_name| {
+        is_scalar_type(type_name.trim_start_matches('?').trim_end_matches("[]"))
+    })
+}
+
+fn global_line(context: &TycoContext, name: &str, value: &TycoValue) -> String {
+    let type_name = global_type(context, name, value).unwrap_or_else(|| "?str".to_string());
+    format!("{type_name} {name}: {}\n", value_text(value))
+}
+
+fn write_struct(out: &mut String, struct_def: &TycoStruct) {
+    out.push_str(&format!("{}:\n", struct_def.name()));
+    for field in struct_def.fields() {
+        out.push_str(&field_line(field));
+    }
+    for instance in struct_def.instances() {
+        out.push_str(&format!("  - {}\n", instance_row(struct_def, instance)));
+    }
+}
+
+/// `  str os: Fedora`, with the `*` or `?` marker taking the place of the
+/// first space.
+fn field_line(field: &FieldSchema) -> String {
+    let marker = if field.is_primary_key {
+        " *"
+    } else if field.is_nullable {
+        " ?"
+    } else {
+        "  "
+    };
+    let declaration = format!("{marker}{} {}:", field_type_name(field), field.name);
+    match &field.default_value {
+        Some(default) => format!("{declaration} {}\n", value_text(default)),
+        None => format!("{declaration}\n"),
+    }
+}
+
+/// Primary key fields leading the schema are written positionally, the
+/// rest by name: `prod-01-us, cores: 64`.
+fn instance_row(struct_def: &TycoStruct, instance: &TycoInstance) -> String {
+    let mut positional = true;
+    struct_def
+        .fields()
+        .iter()
+        .filter_map(|field| {
+            let Some(value) = instance.get_attribute(&field.name) else {
+                positional = false;
+                return None;
+            };
+            positional &= field.is_primary_key;
+            Some(if positional {
+                value_text(value)
+            } else {
+                format!("{}: {}", field.name, value_text(value))
+            })
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A value as the parser reads it for its declared type.
+fn value_text(value: &TycoValue) -> String {
+    match value {
+        TycoValue::Float(v) => format_float(*v),
+        TycoValue::String(s) => string_text(s),
+        TycoValue::Date(v) | TycoValue::Time(v) | TycoValue::DateTime(v) => {
+            string_text(&TycoString::new(v.clone(), false, false))
+        }
+        TycoValue::Bytes(v) => format!("b64\"{}\"", encode_base64(v)),
+        TycoValue::Array(items) => {
+            let items = items.iter().map(value_text).collect::<Vec<_>>();
+            format!("[{}]", items.join(", "))
+        }
+        TycoValue::Instance(instance) => {
+            let fields = instance
+                .attributes()
+                .iter()
+                .map(|(field, value)| format!("{field}: {}", value_text(value)))
+                .collect::<Vec<_>>();
+            format!("{}({})", instance.struct_name(), fields.join(", "))
+        }
+        TycoValue::Reference(reference) => {
+            format!("{}({})", reference.struct_name, reference.primary_key)
+        }
+        other => other.to_template_text(),
+    }
+}
+
+/// Bare text where the parser would read it back unchanged, otherwise a
+/// quoted string: single quotes for literal strings, which are never
+/// rendered, and double quotes with escapes for the rest.
+fn string_text(s: &TycoString) -> String {
+    let text = &s.value;
+    let special = [
+        '"', '\'', '#', ',', ':', '[', ']', '(', ')', '\\', '\n', '\r', '\t',
+    ];
+    let braces = text.contains(['{', '}']);
+    let bare = !text.is_empty()
+        && text.trim() == text
+        && !text.contains(special)
+        && !text.starts_with(['-', '+'])
+        && !text.eq_ignore_ascii_case("null")
+        && !(braces && (s.is_literal || !s.has_template));
+    if bare {
+        return text.clone();
+    }
+    let escapes_quote = text.ends_with('\\');
+    if s.is_literal && !escapes_quote && !text.contains(['\'', '\n', '\r']) {
+        return format!("'{text}'");
+    }
+    if s.is_literal && !escapes_quote && !text.contains("'''") && !text.ends_with('\'') {
+        return format!("'''{text}'''");
+    }
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{escaped}\"")
+}
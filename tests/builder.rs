@@ -0,0 +1,169 @@
+use serde_json::json;
+use tyco_rust::{loads, Change, TycoContext, TycoError, TycoReference};
+
+#[test]
+fn builder_constructs_rendered_contexts() {
+    let context = TycoContext::builder()
+        .global("timezone", "UTC")
+        .global("ports", vec![80, 443])
+        .typed_global("alias", "?str", None::<&str>)
+        .global("primary", TycoReference::new("Host", "prod-01"))
+        .struct_def("Host", |s| {
+            s.pk_str("hostname")
+                .int("cores")
+                .float("load")
+                .date("since")
+                .field("tags", "str[]")
+                .default("cores", 4)
+                .default("load", 1)
+                .default("tags", Vec::<&str>::new())
+        })
+        .struct_def("Application", |s| {
+            s.pk_str("service")
+                .reference("host", "Host")
+                .field("backup", "?Host")
+        })
+        .instance("Host", |i| {
+            i.set("hostname", "prod-01")
+                .set("cores", 16)
+                .set("since", "2024-01-31")
+        })
+        .instance("Host", |i| {
+            i.set("hostname", "prod-02")
+                .set("since", "2024-02-29")
+                .set("tags", vec!["ssd"])
+        })
+        .instance("Application", |i| {
+            i.set("service", "web")
+                .set("host", TycoReference::new("Host", "prod-02"))
+        })
+        .build()
+        .unwrap();
+
+    let json = context.to_json();
+    assert_eq!(json["timezone"], "UTC");
+    assert_eq!(json["ports"], json!([80, 443]));
+    assert_eq!(json["alias"], json!(null));
+    assert_eq!(json["primary"]["cores"], 16);
+    assert_eq!(
+        json["Host"][1],
+        json!({"hostname": "prod-02", "cores": 4, "load": 1.0, "since": "2024-02-29", "tags": ["ssd"]})
+    );
+    assert_eq!(json["Application"][0]["host"]["hostname"], "prod-02");
+    assert_eq!(json["Application"][0]["backup"], json!(null));
+
+    let mut context = context;
+    context.apply_override("ports=+[8080]").unwrap();
+    assert_eq!(context.to_json()["ports"], json!([80, 443, 8080]));
+
+    // Declared nullability carries over to patches.
+    context
+        .apply_patch(&[
+            Change::set("alias", "www"),
+            Change::set("alias", None::<&str>),
+        ])
+        .unwrap();
+    assert!(context
+        .apply_patch(&[Change::set("timezone", None::<&str>)])
+        .is_err());
+    let mut parsed = loads("?str alias: www\nstr timezone: UTC\n").unwrap();
+    parsed
+        .apply_patch(&[Change::set("alias", None::<&str>)])
+        .unwrap();
+    assert!(parsed
+        .apply_patch(&[Change::set("timezone", None::<&str>)])
+        .is_err());
+}
+
+#[test]
+fn builder_validates_as_it_goes() {
+    let hosts = || TycoContext::builder().struct_def("Host", |s| s.pk_str("hostname").int("cores"));
+    let message = |result: Result<TycoContext, TycoError>| result.unwrap_err().to_string();
+
+    // Strings written as literals of the declared type are accepted.
+    let context = hosts()
+        .instance("Host", |i| i.set("hostname", "a").set("cores", "0x10"))
+        .build()
+        .unwrap();
+    assert_eq!(context.to_json()["Host"][0]["cores"], 16);
+
+    assert_eq!(
+        message(
+            hosts()
+                .instance("Host", |i| i.set("hostname", "a").set("cores", "many"))
+                .build()
+        ),
+        "Type mismatch for field 'Host.cores': expected int, found str"
+    );
+    assert_eq!(
+        message(hosts().instance("Host", |i| i.set("hostname", "a")).build()),
+        "Schema error: Host instance is missing required field 'cores'"
+    );
+    assert_eq!(
+        message(
+            hosts()
+                .instance("Host", |i| i
+                    .set("hostname", "a")
+                    .set("cores", 1)
+                    .set("os", "linux"))
+                .build()
+        ),
+        "Schema error: Field 'Host.os' is not declared"
+    );
+    assert_eq!(
+        message(
+            hosts()
+                .instance("Host", |i| i.set("hostname", "a").set("cores", 1))
+                .instance("Host", |i| i.set("hostname", "a").set("cores", 2))
+                .build()
+        ),
+        "Schema error: Duplicate primary key Host(a)"
+    );
+    // The first problem is reported, even when later calls fail too.
+    assert_eq!(
+        message(
+            hosts()
+                .instance("Rack", |i| i.set("name", "r1"))
+                .struct_def("Host", |s| s.str("name"))
+                .build()
+        ),
+        "Unknown struct 'Rack'"
+    );
+    assert_eq!(
+        message(
+            hosts()
+                .struct_def("Application", |s| s
+                    .pk_str("service")
+                    .reference("host", "Server"))
+                .build()
+        ),
+        "Schema error: Field 'Application.host' has unknown type 'Server'"
+    );
+    assert_eq!(
+        message(
+            TycoContext::builder()
+                .struct_def("Host", |s| s.int("cores").int("cores"))
+                .build()
+        ),
+        "Schema error: Field 'Host.cores' is already defined"
+    );
+    assert_eq!(
+        message(
+            TycoContext::builder()
+                .global("empty", Vec::<i64>::new())
+                .build()
+        ),
+        "Schema error: Cannot infer the type of global 'empty'; use typed_global"
+    );
+    assert!(matches!(
+        hosts()
+            .struct_def("Application", |s| s
+                .pk_str("service")
+                .reference("host", "Host"))
+            .instance("Application", |i| i
+                .set("service", "web")
+                .set("host", TycoReference::new("Host", "missing")))
+            .build(),
+        Err(TycoError::Reference(_))
+    ));
+}
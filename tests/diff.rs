@@ -0,0 +1,158 @@
+use serde_json::json;
+use tyco_rust::{diff, loads, Change, FieldSchema, TycoInstance, TycoValue};
+
+const OLD: &str = r#"
+str timezone: UTC
+str owner: ops
+int[] ports: [80, 443]
+
+Host:
+ *str hostname:
+  str os: Fedora
+  int cores:
+  - prod-01-us, cores: 16
+  - prod-02-us, cores: 8
+  - prod-03-eu, cores: 4
+
+Job:
+  str name:
+  - backup
+
+Rack:
+ *str name:
+  - r1
+"#;
+
+const NEW: &str = r#"
+str timezone: Europe/Berlin
+int[] ports: [80, 0x1BB]
+str region: us
+
+Host:
+ *str hostname:
+  str os: Fedora
+  uint cores:
+  int memory: 64
+  ?str notes:
+  - prod-01-us, cores: 16, memory: 128
+  - prod-02-us, os: Debian, cores: 8
+  - prod-04-eu, cores: 2
+
+Job:
+  str name:
+  - rotate
+"#;
+
+#[test]
+fn diff_reports_semantic_changes() {
+    let changes = diff(&loads(OLD).unwrap(), &loads(NEW).unwrap());
+    let lines = changes.iter().map(Change::to_string).collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        [
+            "~ timezone: UTC -> Europe/Berlin",
+            "- owner = ops",
+            "+ region = us",
+            "- Rack[r1]",
+            "- field Rack.name: *str",
+            "- Host[prod-03-eu]",
+            "~ field Host.cores: int -> uint",
+            "+ field Host.memory: int",
+            "+ field Host.notes: ?str",
+            "~ Host[prod-01-us].memory: null -> 128",
+            "~ Host[prod-02-us].os: Fedora -> Debian",
+            "+ Host[prod-04-eu] Host(hostname: prod-04-eu, os: Fedora, cores: 2, memory: 64)",
+            "~ Job[0].name: backup -> rotate",
+        ]
+    );
+    assert!(matches!(
+        &changes[10],
+        Change::ValueChanged { path, .. } if path == "Host[prod-02-us].os"
+    ));
+    assert!(diff(&loads(OLD).unwrap(), &loads(OLD).unwrap()).is_empty());
+}
+
+#[test]
+fn apply_patch_replays_a_diff() {
+    let (old, new) = (loads(OLD).unwrap(), loads(NEW).unwrap());
+    let mut patched = old.clone();
+    patched.apply_patch(&diff(&old, &new)).unwrap();
+    assert!(diff(&patched, &new).is_empty());
+    assert_eq!(patched.to_json()["ports"], new.to_json()["ports"]);
+    assert_eq!(patched.to_json()["Host"][2]["cores"], 2);
+}
+
+#[test]
+fn apply_patch_checks_changes_against_the_schema() {
+    let mut context = loads(OLD).unwrap();
+    let mut host = TycoInstance::new("Host");
+    host.set_attribute("hostname", TycoValue::from("prod-05-eu"));
+    host.set_attribute("cores", TycoValue::from("0x20"));
+    let mut memory = FieldSchema::new("memory", "int");
+    memory.default_value = Some(TycoValue::from(32));
+    context
+        .apply_patch(&[
+            Change::set("Host.os", "Debian"),
+            Change::set("Host[prod-01-us].cores", "24"),
+            Change::FieldAdded {
+                struct_name: "Host".to_string(),
+                field: memory,
+            },
+            Change::InstanceAdded {
+                path: "Host[prod-05-eu]".to_string(),
+                instance: host,
+            },
+            Change::remove_instance("Host[prod-03-eu]"),
+        ])
+        .unwrap();
+    let json = context.to_json();
+    assert_eq!(
+        json["Host"][0],
+        json!({"hostname": "prod-01-us", "os": "Debian", "cores": 24, "memory": 32})
+    );
+    assert_eq!(
+        json["Host"][2],
+        json!({"hostname": "prod-05-eu", "os": "Debian", "cores": 32, "memory": 32})
+    );
+    assert_eq!(json["Host"].as_array().unwrap().len(), 3);
+
+    let before = context.to_json();
+    for (change, message) in [
+        (
+            Change::set("Host[prod-02-us].cores", "many"),
+            "Type mismatch for field 'Host.cores': expected int, found str",
+        ),
+        (
+            Change::set("Host[prod-02-us].hostname", "prod-09"),
+            "Cannot change 'Host.hostname': primary key fields cannot be changed",
+        ),
+        (
+            Change::InstanceAdded {
+                path: "Host[prod-02-us]".to_string(),
+                instance: context.get_struct("Host").unwrap().instances()[1].clone(),
+            },
+            "Duplicate primary key Host(prod-02-us)",
+        ),
+        (
+            Change::FieldAdded {
+                struct_name: "Host".to_string(),
+                field: FieldSchema::new("rack", "str"),
+            },
+            "Host[prod-01-us] is missing required field 'rack'",
+        ),
+        (
+            Change::remove_instance("Host[prod-03-eu]"),
+            "no such value 'Host[prod-03-eu]'",
+        ),
+        (
+            Change::set("timezone", None::<&str>),
+            "Type mismatch for field 'timezone': expected str, found null",
+        ),
+    ] {
+        let err = context
+            .apply_patch(&[Change::set("timezone", "UTC+1"), change])
+            .unwrap_err();
+        assert!(err.to_string().contains(message), "{err}");
+    }
+    assert_eq!(context.to_json(), before);
+}
@@ -0,0 +1,418 @@
+use tyco_rust::{MemoryLoader, RedefinitionPolicy, TycoError, TycoParser, TycoStruct, TycoValue};
+
+const PORTS: &str = r#"
+Port:
+ *str name:
+  int number:
+  - http, 80
+  - https, 443
+"#;
+
+fn port_number(parser: &mut TycoParser, path: &str, name: &str) -> i64 {
+    let context = parser.parse_file(path).unwrap();
+    let port = context
+        .get_struct("Port")
+        .unwrap()
+        .find_by_primary_key(name)
+        .unwrap();
+    match port.get_attribute("number") {
+        Some(TycoValue::Int(number)) => *number,
+        other => panic!("unexpected port number: {other:?}"),
+    }
+}
+
+#[test]
+fn includes_resolve_from_memory() {
+    let loader = MemoryLoader::new()
+        .with_file(
+            "app/main.tyco",
+            "#include \"../shared/ports.tyco\"\nstr env: prod\n",
+        )
+        .with_file("shared/ports.tyco", PORTS);
+    let mut parser = TycoParser::with_loader(loader);
+    assert_eq!(port_number(&mut parser, "app/main.tyco", "https"), 443);
+}
+
+#[test]
+fn include_search_paths() {
+    let loader = MemoryLoader::new()
+        .with_file("app/main.tyco", "#include <common/ports.tyco>\n")
+        .with_file("app/local.tyco", "#include \"common/ports.tyco\"\n")
+        .with_file("app/common/ports.tyco", PORTS.replace("80", "8080"))
+        .with_file("lib/tyco/common/ports.tyco", PORTS);
+
+    let mut parser = TycoParser::with_loader(loader.clone());
+    parser.add_include_path("lib/tyco");
+    assert_eq!(port_number(&mut parser, "app/main.tyco", "http"), 80);
+
+    // Quoted includes prefer the file next to the including file.
+    let mut parser = TycoParser::with_loader(loader.clone());
+    parser.add_include_path("lib/tyco");
+    assert_eq!(port_number(&mut parser, "app/local.tyco", "http"), 8080);
+
+    let mut parser = TycoParser::with_loader(loader);
+    match parser.parse_file("app/main.tyco").unwrap_err() {
+        TycoError::Parse { message, span } => {
+            assert_eq!(message, "Included file 'common/ports.tyco' not found");
+            assert_eq!(span.unwrap().line, 1);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn glob_and_directory_includes() {
+    let loader = MemoryLoader::new()
+        .with_file(
+            "main.tyco",
+            "#include \"ports.tyco\"\n#include \"conf.d/*.tyco\"\n",
+        )
+        .with_file("ports.tyco", PORTS)
+        .with_file(
+            "conf.d/20-web.tyco",
+            "Service:\n *str name:\n  Port port:\n  - web, Port(https)\n",
+        )
+        .with_file(
+            "conf.d/10-ssh.tyco",
+            "Port:\n *str name:\n  int number:\n  - ssh, 22\n\nService:\n *str name:\n  Port port:\n  - ssh, Port(ssh)\n",
+        )
+        .with_file("conf.d/notes.txt", "not tyco");
+
+    let context = TycoParser::with_loader(loader.clone())
+        .parse_file("main.tyco")
+        .unwrap();
+    let ports = context.get_struct("Port").unwrap();
+    assert_eq!(ports.fields().len(), 2);
+    assert_eq!(ports.instances().len(), 3);
+    let services = context
+        .get_struct("Service")
+        .unwrap()
+        .instances()
+        .iter()
+        .map(|service| service.get_attribute("name").unwrap().to_template_text())
+        .collect::<Vec<_>>();
+    assert_eq!(services, ["ssh", "web"]);
+
+    let loader = loader.with_file(
+        "main.tyco",
+        "#include \"ports.tyco\"\n#include \"conf.d/\"\n",
+    );
+    let context = TycoParser::with_loader(loader)
+        .parse_file("main.tyco")
+        .unwrap();
+    assert_eq!(context.get_struct("Service").unwrap().instances().len(), 2);
+}
+
+#[test]
+fn glob_includes_skip_the_including_file_and_report_empty_matches() {
+    let loader = MemoryLoader::new()
+        .with_file("conf/main.tyco", "#include \"*.tyco\"\n#include \"extra/*.tyco\"\n")
+        .with_file("conf/ports.tyco", PORTS)
+        .with_file("conf/extra/readme.txt", "");
+    let context = TycoParser::with_loader(loader.clone())
+        .parse_file("conf/main.tyco")
+        .unwrap();
+    assert_eq!(context.get_struct("Port").unwrap().instances().len(), 2);
+    let diagnostics = context.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].message,
+        "Include \"extra/*.tyco\" matched no files"
+    );
+    assert_eq!(diagnostics[0].span.as_ref().unwrap().line, 2);
+
+    let loader = loader.with_file("conf/main.tyco", "#include \"*/ports.tyco\"\n");
+    let err = TycoParser::with_loader(loader)
+        .parse_file("conf/main.tyco")
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Wildcards are only allowed in the file name of an include: '*/ports.tyco'"));
+}
+
+#[test]
+fn include_cycles_and_repeated_includes() {
+    let loader = MemoryLoader::new()
+        .with_file("main.tyco", "#include \"a.tyco\"\n")
+        .with_file("a.tyco", "#include \"b.tyco\"\n")
+        .with_file("b.tyco", "str b: b\n#include \"a.tyco\"\n");
+    let err = TycoParser::with_loader(loader)
+        .parse_file("main.tyco")
+        .unwrap_err();
+    assert!(matches!(&err, TycoError::IncludeCycle(chain) if chain.len() == 3));
+    assert_eq!(err.to_string(), "Include cycle: a.tyco -> b.tyco -> a.tyco");
+
+    let loader = MemoryLoader::new()
+        .with_file(
+            "main.tyco",
+            "#include \"ports.tyco\"\n#include \"web.tyco\"\n",
+        )
+        .with_file("web.tyco", "#include \"ports.tyco\"\n")
+        .with_file("ports.tyco", PORTS);
+    match TycoParser::with_loader(loader.clone())
+        .parse_file("main.tyco")
+        .unwrap_err()
+    {
+        TycoError::Parse { message, span } => {
+            assert_eq!(
+                message,
+                "'ports.tyco' is already included (first included at main.tyco:1); \
+                 use #include_once to skip repeated includes"
+            );
+            let span = span.unwrap();
+            assert_eq!(span.path.unwrap().to_str(), Some("web.tyco"));
+            assert_eq!(span.line, 1);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+
+    let loader = loader.with_file("web.tyco", "#include_once \"ports.tyco\"\n");
+    let mut parser = TycoParser::with_loader(loader);
+    // State is reset between parses, so the same parser can be reused.
+    for _ in 0..2 {
+        assert_eq!(port_number(&mut parser, "main.tyco", "http"), 80);
+    }
+}
+
+#[test]
+fn source_files_and_depfile() {
+    let loader = MemoryLoader::new()
+        .with_file("main.tyco", "str env: prod\n#include \"conf.d/\"\n")
+        .with_file("conf.d/a.tyco", "#include_once \"../ports.tyco\"\n")
+        .with_file("conf.d/b.tyco", "\n#include_once \"../ports.tyco\"\n")
+        .with_file("ports.tyco", PORTS);
+    let context = TycoParser::with_loader(loader)
+        .parse_file("main.tyco")
+        .unwrap();
+
+    let files = context
+        .source_files()
+        .iter()
+        .map(|file| {
+            let sites = file
+                .included_from
+                .iter()
+                .map(|span| (span.path.clone().unwrap(), span.line))
+                .collect::<Vec<_>>();
+            (file.path.to_str().unwrap(), sites)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        files,
+        [
+            ("main.tyco", vec![]),
+            ("conf.d/a.tyco", vec![("main.tyco".into(), 2)]),
+            (
+                "ports.tyco",
+                vec![("conf.d/a.tyco".into(), 1), ("conf.d/b.tyco".into(), 2)]
+            ),
+            ("conf.d/b.tyco", vec![("main.tyco".into(), 2)]),
+        ]
+    );
+    assert_eq!(context.source_dirs(), [std::path::Path::new("conf.d")]);
+    assert_eq!(
+        context.depfile("out/app config.json"),
+        "out/app\\ config.json: main.tyco conf.d/a.tyco ports.tyco conf.d/b.tyco conf.d\n"
+    );
+    assert!(tyco_rust::loads("str env: prod\n")
+        .unwrap()
+        .source_files()
+        .is_empty());
+}
+
+const NET: &str = r#"
+str timezone: UTC
+Port:
+ *str name:
+  int number:
+  - http_web, 80
+  - https_web, 443
+
+Listener:
+ *str name:
+  Port port:
+  - public, Port(https_web)
+"#;
+
+#[test]
+fn imports_load_into_a_namespace() {
+    let loader = MemoryLoader::new()
+        .with_file(
+            "main.tyco",
+            r#"#import "lib/net.tyco" as net
+str timezone: {net.timezone}
+net.Port default_port: net.Port(http_web)
+
+Port:
+ *str name:
+  int number:
+  - admin, 8443
+
+Service:
+ *str name:
+  net.Port port:
+  str zone: {name}@{global.net.timezone}
+  - web, net.Port(http_web)
+"#,
+        )
+        .with_file("lib/net.tyco", NET);
+    let context = TycoParser::with_loader(loader.clone())
+        .parse_file("main.tyco")
+        .unwrap();
+
+    assert_eq!(context.globals()["timezone"].to_template_text(), "UTC");
+    assert_eq!(context.get_struct("Port").unwrap().instances().len(), 1);
+    let ports = context.get_struct("net.Port").unwrap();
+    assert!(ports.find_by_primary_key("https_web").is_some());
+
+    let json = context.to_json();
+    assert_eq!(json["default_port"]["number"], 80);
+    assert_eq!(json["Service"][0]["port"]["number"], 80);
+    assert_eq!(json["Service"][0]["zone"], "web@UTC");
+    assert_eq!(json["net.Listener"][0]["port"]["number"], 443);
+    assert_eq!(
+        context.source_files()[1]
+            .included_from
+            .iter()
+            .map(|span| span.line)
+            .collect::<Vec<_>>(),
+        [1]
+    );
+
+    let loader = loader.with_file(
+        "lib/net.tyco",
+        format!("#import \"../main.tyco\" as app\n{NET}"),
+    );
+    let err = TycoParser::with_loader(loader)
+        .parse_file("main.tyco")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Include cycle: main.tyco -> lib/net.tyco -> main.tyco"
+    );
+}
+
+#[test]
+fn redefinition_policy() {
+    let loader = MemoryLoader::new()
+        .with_file(
+            "main.tyco",
+            "str region: us-east\n#include \"ports.tyco\"\n#include \"override.tyco\"\n",
+        )
+        .with_file("ports.tyco", PORTS)
+        .with_file(
+            "override.tyco",
+            "str region: eu-west\nPort:\n *str name:\n  int number: 0\n  - ssh, 22\n",
+        );
+
+    let mut parser = TycoParser::with_loader(loader.clone());
+    parser.set_redefinition_policy(RedefinitionPolicy::Override);
+    let context = parser.parse_file("main.tyco").unwrap();
+    assert_eq!(context.globals()["region"].to_template_text(), "eu-west");
+    assert!(context.diagnostics().is_empty());
+
+    let mut parser = TycoParser::with_loader(loader.clone());
+    assert_eq!(parser.redefinition_policy(), RedefinitionPolicy::Warn);
+    let context = parser.parse_file("main.tyco").unwrap();
+    let warnings = context
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| {
+            let span = diagnostic.span.as_ref().unwrap();
+            let previous = diagnostic.previous.as_ref().unwrap();
+            (diagnostic.message.as_str(), span.line, previous.line)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        warnings,
+        [
+            ("Global 'region' is already defined at main.tyco:1", 1, 1),
+            (
+                "Field 'Port.number' is already defined at ports.tyco:4",
+                4,
+                4
+            ),
+        ]
+    );
+
+    let mut parser = TycoParser::with_loader(loader.clone());
+    parser.set_redefinition_policy(RedefinitionPolicy::Error);
+    match parser.parse_file("main.tyco").unwrap_err() {
+        TycoError::Parse { message, span } => {
+            assert_eq!(message, "Global 'region' is already defined at main.tyco:1");
+            let span = span.unwrap();
+            assert_eq!(span.path.unwrap().to_str(), Some("override.tyco"));
+            assert_eq!(span.line, 1);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+
+    // Retyping a field would leave the earlier instances with values of the
+    // old type, so it fails under every policy.
+    let loader = loader.with_file(
+        "override.tyco",
+        "Port:\n *str name:\n  str number:\n  - ssh, 22\n",
+    );
+    let mut parser = TycoParser::with_loader(loader);
+    parser.set_redefinition_policy(RedefinitionPolicy::Override);
+    match parser.parse_file("main.tyco").unwrap_err() {
+        TycoError::Parse { message, span } => {
+            assert_eq!(
+                message,
+                "Field 'Port.number' cannot change type from int to str after Port instances are declared"
+            );
+            assert_eq!(span.unwrap().line, 3);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn redefinition_policy_for_setters() {
+    let mut context = tyco_rust::loads("str region: us-east\n").unwrap();
+    let region = |context: &tyco_rust::TycoContext| context.globals()["region"].to_template_text();
+
+    context
+        .try_set_global("region", "us-east".into(), RedefinitionPolicy::Error)
+        .unwrap();
+    match context
+        .try_set_global("region", "eu-west".into(), RedefinitionPolicy::Error)
+        .unwrap_err()
+    {
+        TycoError::Schema(message) => assert_eq!(message, "Global 'region' is already defined"),
+        other => panic!("unexpected error: {other}"),
+    }
+    assert_eq!(region(&context), "us-east");
+
+    context
+        .try_set_global("region", "eu-west".into(), RedefinitionPolicy::Warn)
+        .unwrap();
+    assert_eq!(region(&context), "eu-west");
+    context
+        .try_add_struct(TycoStruct::new("Port"), RedefinitionPolicy::Error)
+        .unwrap();
+    context
+        .try_add_struct(TycoStruct::new("Port"), RedefinitionPolicy::Warn)
+        .unwrap();
+    assert!(context
+        .try_add_struct(TycoStruct::new("Port"), RedefinitionPolicy::Error)
+        .is_err());
+    let warnings = context
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| diagnostic.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        warnings,
+        [
+            "Global 'region' is already defined",
+            "Struct 'Port' is already defined"
+        ]
+    );
+
+    context
+        .try_set_global("region", "ap-south".into(), RedefinitionPolicy::Override)
+        .unwrap();
+    assert_eq!(region(&context), "ap-south");
+    assert_eq!(context.diagnostics().len(), 2);
+}
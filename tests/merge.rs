@@ -0,0 +1,304 @@
+use tyco_rust::{diff, loads, merge, merge_sources, Change};
+
+const BASE: &str = r#"
+str domain: example.com
+
+Host:
+ *str hostname:
+  str os: Fedora
+  int cores:
+  str fqdn: {hostname}.{global.domain}
+  - web-01, cores: 4
+  - web-02, cores: 8
+
+Job:
+  str name:
+  - backup
+"#;
+
+#[test]
+fn merge_combines_independent_changes() {
+    let base = loads(BASE).unwrap();
+    let ours = loads(&BASE.replace(
+        "  - web-02, cores: 8",
+        "  - web-02, cores: 8\n  - web-03, cores: 2\n  - db-01, cores: 32",
+    ))
+    .unwrap();
+    let theirs = loads(
+        &BASE
+            .replace("str domain: example.com", "str domain: corp.example.com")
+            .replace("- web-01, cores: 4", "- web-01, os: Debian, cores: 4")
+            .replace(
+                "  - web-02, cores: 8",
+                "  - web-02, cores: 8\n  - web-04, cores: 16",
+            )
+            .replace("  - backup", "  - backup\n  - rotate"),
+    )
+    .unwrap();
+
+    let outcome = merge(&base, &ours, &theirs).unwrap();
+    assert!(outcome.is_clean(), "{:?}", outcome.conflicts);
+    let json = outcome.context.to_json();
+    let hosts = json["Host"].as_array().unwrap();
+    assert_eq!(
+        hosts
+            .iter()
+            .map(|host| host["hostname"].clone())
+            .collect::<Vec<_>>(),
+        ["web-01", "web-02", "web-03", "db-01", "web-04"]
+    );
+    assert_eq!(hosts[0]["os"], "Debian");
+    assert_eq!(hosts[3]["fqdn"], "db-01.corp.example.com");
+    let jobs = outcome.context.get_struct("Job").unwrap().instances();
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[1].get_as::<String>("name").unwrap(), "rotate");
+
+    // Both sides making the same change is not a conflict.
+    let outcome = merge(&base, &theirs, &theirs).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(outcome.context.to_json(), theirs.to_json());
+}
+
+#[test]
+fn merge_reports_conflicts_with_paths() {
+    let base = loads(BASE).unwrap();
+    let ours = loads(
+        &BASE
+            .replace("- web-01, cores: 4", "- web-01, cores: 6")
+            .replace("  - web-02, cores: 8\n", ""),
+    )
+    .unwrap();
+    let theirs = loads(
+        &BASE
+            .replace("- web-01, cores: 4", "- web-01, cores: 12")
+            .replace("- web-02, cores: 8", "- web-02, os: Debian, cores: 8"),
+    )
+    .unwrap();
+
+    let outcome = merge(&base, &ours, &theirs).unwrap();
+    let paths = outcome
+        .conflicts
+        .iter()
+        .map(|conflict| conflict.path.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["Host[web-01].cores", "Host[web-02].os"]);
+    assert!(matches!(
+        &outcome.conflicts[1].ours,
+        Change::InstanceRemoved { path, .. } if path == "Host[web-02]"
+    ));
+    assert_eq!(
+        outcome.conflicts[0].to_string(),
+        "conflict at Host[web-01].cores:\n  ours:   ~ Host[web-01].cores: 4 -> 6\n  theirs: ~ Host[web-01].cores: 4 -> 12"
+    );
+    // Conflicting values keep our side.
+    assert_eq!(outcome.context.to_json()["Host"][0]["cores"], 6);
+    assert_eq!(
+        outcome.context.to_json()["Host"].as_array().unwrap().len(),
+        1
+    );
+}
+
+#[test]
+fn merge_applies_default_changes_to_added_instances() {
+    let base = loads(BASE).unwrap();
+    let ours = loads(&BASE.replace(
+        "  - web-02, cores: 8",
+        "  - web-02, cores: 8\n  - web-03, cores: 2",
+    ))
+    .unwrap();
+    let theirs = loads(&BASE.replace("str os: Fedora", "str os: Debian")).unwrap();
+
+    let changes = diff(&base, &theirs);
+    let lines = changes.iter().map(Change::to_string).collect::<Vec<_>>();
+    assert_eq!(lines, ["~ default Host.os: Fedora -> Debian"]);
+
+    let outcome = merge(&base, &ours, &theirs).unwrap();
+    assert!(outcome.is_clean(), "{:?}", outcome.conflicts);
+    let json = outcome.context.to_json();
+    for host in json["Host"].as_array().unwrap() {
+        assert_eq!(host["os"], "Debian");
+    }
+
+    // Both sides changing the default differently conflicts.
+    let ours = loads(&BASE.replace("str os: Fedora", "str os: Alpine")).unwrap();
+    let outcome = merge(&base, &ours, &theirs).unwrap();
+    let paths = outcome
+        .conflicts
+        .iter()
+        .map(|conflict| conflict.path.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["Host.os"]);
+    assert_eq!(outcome.context.to_json()["Host"][0]["os"], "Alpine");
+}
+
+#[test]
+fn merge_conflicts_on_additions_to_removed_structs() {
+    let base = loads(BASE).unwrap();
+    let without_jobs = BASE.split("\nJob:").next().unwrap().to_string();
+    let with_more_jobs = BASE
+        .replace("  str name:\n", "  str name:\n  ?str schedule:\n")
+        .replace("  - backup", "  - backup\n  - rotate");
+
+    let ours = loads(&without_jobs).unwrap();
+    let theirs = loads(&with_more_jobs).unwrap();
+    let outcome = merge(&base, &ours, &theirs).unwrap();
+    let paths = outcome
+        .conflicts
+        .iter()
+        .map(|conflict| conflict.path.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["Job.schedule", "Job[1]"]);
+    assert!(outcome.context.get_struct("Job").is_none());
+
+    let outcome = merge(&base, &theirs, &ours).unwrap();
+    let paths = outcome
+        .conflicts
+        .iter()
+        .map(|conflict| conflict.path.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["Job[0]", "Job.name"]);
+    let jobs = outcome.context.get_struct("Job").unwrap().instances();
+    assert_eq!(jobs.len(), 2);
+}
+
+#[test]
+fn merged_context_writes_back_as_tyco() {
+    let base = loads(BASE).unwrap();
+    let ours =
+        loads(&BASE.replace("- web-01, cores: 4", "- web-01, os: 'Debian, 12', cores: 4")).unwrap();
+    let theirs = loads(&BASE.replace("  - backup", "  - backup\n  - rotate")).unwrap();
+    let outcome = merge(&base, &ours, &theirs).unwrap();
+
+    let text = outcome.context.to_tyco().unwrap();
+    assert!(
+        text.contains("  str fqdn: {hostname}.{global.domain}\n"),
+        "{text}"
+    );
+    assert!(
+        text.contains("  - web-01, os: 'Debian, 12', cores: 4\n"),
+        "{text}"
+    );
+    let written = loads(&text).unwrap();
+    assert!(diff(&outcome.context, &written).is_empty(), "{text}");
+    assert_eq!(written.to_json(), outcome.context.to_json());
+}
+
+#[test]
+fn merge_sources_rewrites_only_changed_lines() {
+    let base = r#"# Fleet inventory
+str domain: example.com  # public zone
+
+Host:
+ *str hostname:
+  int cores: 4
+  # web tier
+  - web-01, cores: 8  # oversized
+  - web-02
+  - web-03   # retiring
+
+Job:
+  str name:
+  - backup
+"#;
+    let ours = base.replace("  - web-03   # retiring\n", "");
+    let theirs = base
+        .replace("str domain: example.com", "str domain: corp.example.com")
+        .replace("- web-01, cores: 8", "- web-01, cores: 16")
+        .replace("  - web-02\n", "  - web-02\n  - web-04\n")
+        .replace("  - backup\n", "  - backup\n  - rotate\n");
+
+    let merged = merge_sources(base, &ours, &theirs).unwrap();
+    assert!(merged.outcome.is_clean(), "{:?}", merged.outcome.conflicts);
+    assert_eq!(
+        merged.text,
+        r#"# Fleet inventory
+str domain: corp.example.com  # public zone
+
+Host:
+ *str hostname:
+  int cores: 4
+  # web tier
+  - web-01, cores: 16  # oversized
+  - web-02
+  - web-04
+
+Job:
+  str name:
+  - backup
+  - name: rotate
+"#
+    );
+
+    let included = format!("#include \"hosts.tyco\"\n{base}");
+    match merge_sources(base, &included, &theirs).unwrap_err() {
+        tyco_rust::TycoError::Parse { message, .. } => {
+            assert!(message.contains("Cannot merge ours: line 1"), "{message}")
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn merge_driver_writes_the_result_to_ours() {
+    let dir = std::env::temp_dir().join(format!("tyco-merge-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, content: &str| {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    };
+    let run = |base: &str, ours: &str, theirs: &str| {
+        let paths = [
+            write("base.tyco", base),
+            write("ours.tyco", ours),
+            write("theirs.tyco", theirs),
+        ];
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_tyco-merge"))
+            .args(&paths)
+            .output()
+            .unwrap();
+        (
+            output.status.code(),
+            std::fs::read_to_string(&paths[1]).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    let ours = BASE.replace("- web-01, cores: 4", "- web-01, cores: 6  # resized");
+    let (code, merged, stderr) = run(
+        BASE,
+        &ours,
+        &BASE.replace("- web-02, cores: 8", "- web-02, cores: 10"),
+    );
+    assert_eq!(code, Some(0));
+    assert!(
+        stderr.contains("warning: Host[web-01] is not referenced"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("global 'domain'"), "{stderr}");
+    assert_eq!(
+        merged,
+        ours.replace("- web-02, cores: 8", "- web-02, cores: 10")
+    );
+
+    let (code, merged, _) = run(
+        BASE,
+        &ours,
+        &BASE.replace("- web-01, cores: 4", "- web-01, cores: 12"),
+    );
+    assert_eq!(code, Some(1));
+    assert_eq!(merged, ours);
+
+    // Included files are not part of what git hands the driver, so the
+    // driver leaves the file alone.
+    write("hosts.tyco", "Rack:\n *str name:\n  - r1\n");
+    let ours = format!("#include \"hosts.tyco\"\n# changed locally\n{ours}");
+    let (code, merged, _) = run(
+        BASE,
+        &ours,
+        &BASE.replace("- web-02, cores: 8", "- web-02, cores: 10"),
+    );
+    assert_eq!(code, Some(2));
+    assert_eq!(merged, ours);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
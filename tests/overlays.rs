@@ -0,0 +1,196 @@
+use serde_json::json;
+use tyco_rust::{loads, MemoryLoader, OriginKind, TycoError, TycoParser};
+
+const BASE: &str = r#"
+str environment: base
+str domain: example.com
+str[] admins: [alice]
+
+Host:
+ *str hostname:
+  int cores: 4
+  str fqdn: {hostname}.{global.domain}
+  str[] tags: [linux]
+  - web-01
+  - web-02, 8
+
+Job:
+  str name:
+  - backup
+"#;
+
+const PROD: &str = r#"
+str environment: prod
+str domain: prod.example.com
+str[] admins: +[bob]
+
+Host:
+  cores: 16
+  - web-02, tags: +[ssd]
+  - web-03, 32, tags: [arm]
+
+Job:
+  - rotate
+"#;
+
+#[test]
+fn parse_layers_merges_in_order() {
+    let loader = MemoryLoader::new()
+        .with_file("base.tyco", BASE)
+        .with_file("prod.tyco", PROD);
+    let context = TycoParser::with_loader(loader)
+        .parse_layers(&["base.tyco", "prod.tyco"])
+        .unwrap();
+
+    let json = context.to_json();
+    assert_eq!(json["environment"], "prod");
+    assert_eq!(json["admins"], json!(["alice", "bob"]));
+    assert_eq!(
+        json["Host"],
+        json!([
+            {"hostname": "web-01", "cores": 16, "fqdn": "web-01.prod.example.com", "tags": ["linux"]},
+            {"hostname": "web-02", "cores": 8, "fqdn": "web-02.prod.example.com", "tags": ["linux", "ssd"]},
+            {"hostname": "web-03", "cores": 32, "fqdn": "web-03.prod.example.com", "tags": ["arm"]},
+        ])
+    );
+    let jobs = context.get_struct("Job").unwrap().instances();
+    assert_eq!(jobs.len(), 2);
+
+    assert_eq!(context.layer_of("environment"), Some(1));
+    assert_eq!(context.layer_of("Host.cores"), Some(1));
+    assert_eq!(context.layer_of("Host[web-01].cores"), Some(1));
+    assert_eq!(context.layer_of("Host[web-02].cores"), Some(0));
+    assert_eq!(context.layer_of("Host[web-02].tags"), Some(1));
+    assert_eq!(context.layer_of("Host[web-03].hostname"), Some(1));
+    assert_eq!(context.source_files().len(), 2);
+}
+
+#[test]
+fn overlay_rendered_contexts() {
+    let mut context = loads(BASE).unwrap();
+    let overlay = loads(
+        "str domain: staging.example.com\n\nHost:\n *str hostname:\n  int cores:\n  - web-01, 2\n",
+    )
+    .unwrap();
+    context.overlay(&overlay).unwrap();
+
+    let host = context
+        .get_struct("Host")
+        .unwrap()
+        .find_by_primary_key("web-01")
+        .unwrap();
+    let json = host.to_json_value(&context);
+    assert_eq!(json["cores"], 2);
+    assert_eq!(json["fqdn"], "web-01.staging.example.com");
+    assert_eq!(
+        context.globals()["admins"].to_json_value_in(&context),
+        json!(["alice"])
+    );
+    assert_eq!(context.layer_of("Host[web-01].cores"), Some(1));
+    assert_eq!(context.layer_of("Host[web-02].cores"), Some(0));
+    assert_eq!(context.layer_of("Host[web-09].cores"), None);
+    assert_eq!(context.layer_of("Host[web-01].missing"), None);
+}
+
+#[test]
+fn overlays_render_typed_templates_again() {
+    let content = r#"
+str port: 8000
+str tls: false
+
+Listener:
+  int port:
+  bool tls:
+
+Service:
+ *str name:
+  Listener listener:
+  - web, Listener({global.port}, {global.tls})
+"#;
+    let mut context = loads(content).unwrap();
+    assert_eq!(
+        context.to_json()["Service"][0]["listener"],
+        json!({"port": 8000, "tls": false})
+    );
+
+    context
+        .overlay(&loads("str port: 8443\nstr tls: true\n").unwrap())
+        .unwrap();
+    assert_eq!(
+        context.to_json()["Service"][0]["listener"],
+        json!({"port": 8443, "tls": true})
+    );
+}
+
+#[test]
+fn apply_overrides_from_paths() {
+    let mut context = loads(BASE).unwrap();
+    context
+        .apply_overrides([
+            "domain=staging.example.com",
+            "Host[web-01].cores = 0x10",
+            "Host.tags=+[vm]",
+            "admins=[carol]",
+        ])
+        .unwrap();
+
+    let json = context.to_json();
+    assert_eq!(json["admins"], json!(["carol"]));
+    assert_eq!(json["Host"][0]["cores"], 16);
+    assert_eq!(json["Host"][0]["fqdn"], "web-01.staging.example.com");
+    assert_eq!(json["Host"][1]["cores"], 8);
+    assert_eq!(json["Host"][1]["tags"], json!(["linux", "vm"]));
+
+    let origin = context.explain("Host[web-01].cores").unwrap();
+    assert_eq!(origin.kind, OriginKind::Override);
+    assert_eq!(origin.span.unwrap().column, 22);
+
+    let before = context.to_json();
+    for (assignment, message) in [
+        ("Host[web-02].cores=many", "Invalid numeric literal 'many'"),
+        (
+            "Host[web-09].cores=1",
+            "Cannot override 'Host[web-09].cores': no such global or field",
+        ),
+        (
+            "Host[web-01].hostname=web-09",
+            "Cannot override 'Host.hostname': primary key fields cannot be overridden",
+        ),
+        ("domain", "Override 'domain' must be written as path=value"),
+        (
+            "domain=null",
+            "Cannot override 'domain': the global is not nullable",
+        ),
+        (
+            "Host[web-01].cores=null",
+            "Cannot override 'Host.cores': the field is not nullable",
+        ),
+        (
+            "Host.cores=null",
+            "Cannot override 'Host.cores': the field is not nullable",
+        ),
+    ] {
+        match context
+            .apply_overrides(["environment=test", assignment])
+            .unwrap_err()
+        {
+            TycoError::Parse {
+                message: actual, ..
+            } => {
+                assert!(actual.contains(message), "{actual}")
+            }
+            other => panic!("unexpected error: {other}"),
+        }
+    }
+    assert_eq!(context.to_json(), before);
+
+    let mut context =
+        loads("?str owner: ops\n\nHost:\n *str hostname:\n ?int cores:\n  - a, 4\n").unwrap();
+    context
+        .apply_overrides(["owner=null", "Host[a].cores=null"])
+        .unwrap();
+    assert_eq!(
+        context.to_json(),
+        json!({"owner": null, "Host": [{"hostname": "a", "cores": null}]})
+    );
+}
@@ -0,0 +1,127 @@
+use tyco_rust::{Change, MemoryLoader, OriginKind, TycoParser};
+
+const BASE: &str = r#"str region: us-east
+#include "hosts.tyco"
+
+Application:
+ *str service:
+  Host host:
+  str command: start --port {port}
+  int port: 8000
+  - web, Host(prod-01), port: 8080
+  - worker, Host(prod-02), command: run
+"#;
+
+const HOSTS: &str = r#"Host:
+ *str hostname:
+  int cores: 4
+  - prod-01, 16
+  - prod-02
+"#;
+
+fn loader() -> MemoryLoader {
+    MemoryLoader::new()
+        .with_file("base.tyco", BASE)
+        .with_file("hosts.tyco", HOSTS)
+        .with_file(
+            "prod.tyco",
+            "Host:\n *str hostname:\n  - prod-02, cores: 32\n",
+        )
+}
+
+#[test]
+fn explain_reports_where_values_came_from() {
+    let context = TycoParser::with_loader(loader())
+        .parse_file("base.tyco")
+        .unwrap();
+    let explain = |path: &str| {
+        let origin = context.explain(path).unwrap();
+        let span = origin.span.unwrap();
+        let file = span.path.unwrap().to_string_lossy().into_owned();
+        (origin.kind, file, span.line, span.column)
+    };
+
+    assert_eq!(
+        explain("region"),
+        (OriginKind::Explicit, "base.tyco".into(), 1, 13)
+    );
+    assert_eq!(
+        explain("Host[prod-01].cores"),
+        (OriginKind::Positional, "hosts.tyco".into(), 4, 14)
+    );
+    assert_eq!(
+        explain("Host[prod-02].cores"),
+        (OriginKind::Default, "hosts.tyco".into(), 3, 14)
+    );
+    assert_eq!(
+        explain("Host.cores"),
+        (OriginKind::Default, "hosts.tyco".into(), 3, 14)
+    );
+    assert_eq!(
+        explain("Application[web].port"),
+        (OriginKind::Explicit, "base.tyco".into(), 9, 31)
+    );
+    assert_eq!(
+        explain("Application[worker].command"),
+        (OriginKind::Explicit, "base.tyco".into(), 10, 37)
+    );
+    // References are followed to the field of the target instance.
+    assert_eq!(
+        explain("Application[web].host.cores"),
+        explain("Host[prod-01].cores")
+    );
+
+    let origin = context.explain("Application[web].command").unwrap();
+    assert_eq!(origin.kind, OriginKind::Templated);
+    assert_eq!(origin.template.as_deref(), Some("start --port {port}"));
+    assert_eq!(origin.span.unwrap().line, 7);
+
+    assert!(context.explain("Host[prod-03].cores").is_none());
+    assert!(context.explain("Application[web].missing").is_none());
+    assert!(context.explain("Host[prod-01]").is_none());
+    assert!(context.explain("Host[").is_none());
+}
+
+#[test]
+fn explain_reports_overlay_layers() {
+    let context = TycoParser::with_loader(loader())
+        .parse_layers(&["base.tyco", "prod.tyco"])
+        .unwrap();
+
+    let origin = context.explain("Host[prod-02].cores").unwrap();
+    assert_eq!(origin.kind, OriginKind::Overlay);
+    assert_eq!(origin.layer, 1);
+    let span = origin.span.unwrap();
+    assert_eq!(span.path.unwrap().to_str(), Some("prod.tyco"));
+    assert_eq!(span.line, 3);
+
+    let origin = context.explain("Host[prod-01].cores").unwrap();
+    assert_eq!(origin.kind, OriginKind::Positional);
+    assert_eq!(origin.layer, 0);
+}
+
+#[test]
+fn keys_with_separators_round_trip_through_paths() {
+    let base = tyco_rust::loads(
+        "Site:\n *str name:\n *str city:\n  int racks:\n  - \"Acme, Inc.\", \" Oslo \", 2\n  - plain, Bergen, 1\n",
+    )
+    .unwrap();
+    let sites = base.get_struct("Site").unwrap();
+    let path = sites.instance_path(0, &sites.instances()[0]);
+    assert_eq!(path, "Site['Acme, Inc.', ' Oslo ']");
+    assert_eq!(sites.instance_path(1, &sites.instances()[1]), "Site[plain, Bergen]");
+
+    let racks = format!("{path}.racks");
+    assert_eq!(base.explain(&racks).unwrap().span.unwrap().line, 5);
+    assert_eq!(base.get(&racks).unwrap().to_json_value(&base), 2);
+    assert_eq!(base.get("Site[\"Acme, Inc.\", \" Oslo \"].racks").unwrap().to_json_value(&base), 2);
+
+    let mut changed = base.clone();
+    changed.apply_patch(&[Change::set(&racks, 3)]).unwrap();
+    let changes = tyco_rust::diff(&base, &changed);
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].path(), racks);
+    let mut replayed = base.clone();
+    replayed.apply_patch(&changes).unwrap();
+    assert_eq!(replayed.get(&racks).unwrap().to_json_value(&replayed), 3);
+}
@@ -0,0 +1,160 @@
+use serde_json::json;
+use tyco_rust::{loads, TycoError, TycoValue};
+
+const CONFIG: &str = r#"
+str timezone: UTC
+int[] ports: [80, 443]
+
+Host:
+ *str hostname:
+  str os: Fedora
+  int cores:
+  - prod-01-us, cores: 16
+  - prod-02-us, os: Debian, cores: 8
+  - prod-03-eu, cores: 4
+
+Application:
+ *str service:
+  Host host:
+  Host[] replicas: []
+  - web, Host(prod-01-us), replicas: [Host(prod-02-us), Host(prod-03-eu)]
+  - batch, Host(prod-02-us)
+  - cache, Host(prod-03-eu)
+
+Job:
+  str name:
+  - backup
+"#;
+
+fn texts(values: Vec<tyco_rust::PathMatch<'_>>) -> Vec<String> {
+    values
+        .iter()
+        .map(|found| found.value().unwrap().to_template_text())
+        .collect()
+}
+
+#[test]
+fn get_single_values() {
+    let context = loads(CONFIG).unwrap();
+
+    let timezone = context.get("timezone").unwrap().value().unwrap();
+    assert_eq!(timezone.to_template_text(), "UTC");
+    assert!(matches!(
+        context.get("Host[prod-01-us].cores").unwrap().value(),
+        Some(TycoValue::Int(16))
+    ));
+    assert!(matches!(
+        context.get("ports[1]").unwrap().value(),
+        Some(TycoValue::Int(443))
+    ));
+    assert!(matches!(
+        context
+            .get("Application[web].replicas[1].cores")
+            .unwrap()
+            .value(),
+        Some(TycoValue::Int(4))
+    ));
+
+    let host = context.get("Application[batch].host").unwrap();
+    assert!(matches!(host.value(), Some(TycoValue::Reference(_))));
+    assert_eq!(host.to_json_value(&context)["os"], "Debian");
+
+    let host = context.get("Host[prod-02-us]").unwrap().instance().unwrap();
+    assert_eq!(
+        host.get_attribute("os").unwrap().to_template_text(),
+        "Debian"
+    );
+    assert_eq!(
+        context.get("Host.os").unwrap().to_json_value(&context),
+        json!("Fedora")
+    );
+    assert_eq!(
+        context.get("Job[0].name").unwrap().to_json_value(&context),
+        json!("backup")
+    );
+
+    assert!(context.get("Host[prod-09-us].cores").is_none());
+    assert!(context.get("ports[2]").is_none());
+    assert!(context.get("missing").is_none());
+}
+
+#[test]
+fn query_wildcards_and_filters() {
+    let context = loads(CONFIG).unwrap();
+
+    assert_eq!(
+        texts(context.query("Application[*].service").unwrap()),
+        ["web", "batch", "cache"]
+    );
+    assert_eq!(
+        texts(
+            context
+                .query(r#"Application[?host.os == "Fedora"].service"#)
+                .unwrap()
+        ),
+        ["web", "cache"]
+    );
+    assert_eq!(
+        texts(context.query("Host[?cores >= 8].hostname").unwrap()),
+        ["prod-01-us", "prod-02-us"]
+    );
+    assert_eq!(
+        texts(
+            context
+                .query("Application[?replicas[*].os != 'Fedora'].service")
+                .unwrap()
+        ),
+        ["web"]
+    );
+    assert_eq!(
+        texts(
+            context
+                .query("Application[web].replicas[*].hostname")
+                .unwrap()
+        ),
+        ["prod-02-us", "prod-03-eu"]
+    );
+    assert_eq!(context.query("Host").unwrap().len(), 3);
+    assert!(context.query("Host[?os == Windows]").unwrap().is_empty());
+    assert_eq!(
+        texts(context.query("Host[?cores == 8.0].hostname").unwrap()),
+        ["prod-02-us"]
+    );
+    assert_eq!(context.query("Application[?host]").unwrap().len(), 3);
+    assert!(context
+        .query("Application[?host == web]")
+        .unwrap()
+        .is_empty());
+
+    for path in ["Host[", "Host[?cores >]", "[0]", "Host..cores", ""] {
+        match context.query(path).unwrap_err() {
+            TycoError::Parse { message, .. } => {
+                assert_eq!(message, format!("Invalid path '{path}'"))
+            }
+            other => panic!("unexpected error: {other}"),
+        }
+    }
+}
+
+#[test]
+fn filters_compare_large_integers_exactly() {
+    let context = loads(
+        r#"
+Item:
+ *uint id:
+  int offset:
+  - 9007199254740992, offset: -9007199254740993
+  - 9007199254740993, offset: -9007199254740992
+"#,
+    )
+    .unwrap();
+    let ids = |path: &str| texts(context.query(&format!("{path}.id")).unwrap());
+
+    assert_eq!(ids("Item[?id == 9007199254740992]"), ["9007199254740992"]);
+    assert_eq!(ids("Item[?id > 9007199254740992]"), ["9007199254740993"]);
+    assert_eq!(
+        ids("Item[?offset < -9007199254740992]"),
+        ["9007199254740992"]
+    );
+    assert_eq!(ids("Item[?id > 9.0e15]").len(), 2);
+}
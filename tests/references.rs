@@ -0,0 +1,264 @@
+use serde_json::json;
+use tyco_rust::{loads, ReferenceOwner, TycoError, TycoValue};
+
+const COMPOSITE: &str = r#"
+Host:
+ *str region:
+ *str name:
+  int cores:
+  - us-east, web-01, 8
+  - eu-west, web-01, 16
+
+Application:
+ *str service:
+  Host host:
+  - api, Host(eu-west, web-01)
+"#;
+
+#[test]
+fn composite_primary_keys() {
+    let context = loads(COMPOSITE).unwrap();
+    let hosts = context.get_struct("Host").unwrap();
+    assert_eq!(hosts.primary_key_fields(), ["region", "name"]);
+
+    let host = hosts.find_by_primary_key(["us-east", "web-01"]).unwrap();
+    assert!(matches!(
+        host.get_attribute("cores"),
+        Some(TycoValue::Int(8))
+    ));
+
+    let app = &context.get_struct("Application").unwrap().instances()[0];
+    let host = app.to_json_value(&context)["host"].clone();
+    assert_eq!(host["cores"], json!(16));
+}
+
+#[test]
+fn composite_key_arity_mismatch() {
+    let content = COMPOSITE.replace("Host(eu-west, web-01)", "Host(web-01)");
+    match loads(&content).unwrap_err() {
+        TycoError::Parse { message, span } => {
+            assert!(
+                message.contains("keyed by 2 field(s): region, name"),
+                "{message}"
+            );
+            assert_eq!(span.unwrap().line, 12);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn typed_primary_keys() {
+    let content = r#"
+Port:
+ *int number:
+  str name:
+  - 80, http
+  - 443, https
+
+Weight:
+ *float ratio:
+  - 0.5
+
+Route:
+ *str path:
+  Port port:
+  Weight weight: Weight(5e-1)
+  - /, Port(0x50)
+  - /secure, Port(443)
+"#;
+    let context = loads(content).unwrap();
+    let ports = context.get_struct("Port").unwrap();
+    assert!(ports.find_by_primary_key(80).is_some());
+    assert!(ports.find_by_primary_key("0x1BB").is_some());
+    assert!(ports.find_by_primary_key("8080").is_none());
+
+    let routes = &context.get_struct("Route").unwrap().instances();
+    let json = routes[0].to_json_value(&context);
+    assert_eq!(json["port"]["name"], json!("http"));
+    assert_eq!(json["weight"]["ratio"], json!(0.5));
+
+    assert!(loads(&content.replace("Port(0x50)", "Port(http)")).is_err());
+}
+
+const NESTED: &str = r#"
+Application:
+ *str service:
+  Host host:
+  - web, Host(prod-01)
+  - db, Host(prod-01)
+
+Host:
+ *str hostname:
+  Rack rack:
+  - prod-01, Rack(r1)
+
+Rack:
+ *str name:
+  str region: us-east
+  - r1
+"#;
+
+#[test]
+fn references_share_the_target_instance() {
+    let context = loads(NESTED).unwrap();
+    let apps = context.get_struct("Application").unwrap().instances();
+    let hosts = apps
+        .iter()
+        .map(|app| match app.get_attribute("host") {
+            Some(TycoValue::Reference(reference)) => context.resolve(reference).unwrap(),
+            other => panic!("unexpected host value: {other:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert!(std::ptr::eq(hosts[0], hosts[1]));
+    assert!(std::ptr::eq(
+        hosts[0],
+        context
+            .get_struct("Host")
+            .unwrap()
+            .find_by_primary_key("prod-01")
+            .unwrap()
+    ));
+
+    let json = apps[1].to_json_value(&context);
+    assert_eq!(json["host"]["rack"]["region"], json!("us-east"));
+
+    // Without the context a reference is written as its key.
+    let host = apps[1].get_attribute("host").unwrap();
+    assert_eq!(host.to_json_value_detached(), json!("prod-01"));
+    assert_eq!(host.to_json_value_in(&context)["hostname"], json!("prod-01"));
+}
+
+#[test]
+fn references_follow_instances_that_moved() {
+    let mut context = loads(&NESTED.replace(
+        "  - prod-01, Rack(r1)",
+        "  - prod-00, Rack(r1)\n  - prod-01, Rack(r1)",
+    ))
+    .unwrap();
+    let hosts = context.get_struct_mut("Host").unwrap();
+    hosts.instances_mut().remove(0);
+
+    let app = &context.get_struct("Application").unwrap().instances()[0];
+    let Some(TycoValue::Reference(reference)) = app.get_attribute("host") else {
+        panic!("expected a reference");
+    };
+    let host = context.resolve(reference).unwrap();
+    assert_eq!(host.get_as::<&str>("hostname").unwrap(), "prod-01");
+    assert_eq!(app.to_json_value(&context)["host"]["hostname"], json!("prod-01"));
+
+    context.get_struct_mut("Host").unwrap().instances_mut().clear();
+    let app = &context.get_struct("Application").unwrap().instances()[0];
+    assert_eq!(app.to_json_value(&context)["host"], json!(null));
+}
+
+#[test]
+fn templates_follow_references_transitively() {
+    let content = NESTED
+        .replace(
+            "  Host host:\n",
+            "  Host host:\n  str placement: {service} in {host.rack.label}\n",
+        )
+        .replace(
+            "  str region: us-east\n",
+            "  str region: us-east\n  str label: {name}.{region}\n",
+        );
+    let context = loads(&content).unwrap();
+    let app = &context.get_struct("Application").unwrap().instances()[0];
+    assert_eq!(
+        app.get_attribute("placement").unwrap().to_template_text(),
+        "web in r1.us-east"
+    );
+}
+
+#[test]
+fn reference_cycles_report_the_chain() {
+    let content = NESTED.replace(
+        "  str region: us-east\n  - r1\n",
+        "  Host host: Host(prod-01)\n  - r1\n",
+    );
+    let err = loads(&content).unwrap_err();
+    assert!(matches!(&err, TycoError::ReferenceCycle(_)));
+    assert_eq!(
+        err.to_string(),
+        "Reference cycle: Host(prod-01) -> Rack(r1) -> Host(prod-01)"
+    );
+}
+
+#[test]
+fn long_reference_chains_render() {
+    let mut content = String::from("Node:\n *int id:\n  ?Node next:\n");
+    for id in 0..20_000 {
+        content.push_str(&format!("  - {id}, Node({})\n", id + 1));
+    }
+    content.push_str("  - 20000, null\n");
+    let context = loads(&content).unwrap();
+    assert_eq!(context.get_struct("Node").unwrap().instances().len(), 20_001);
+}
+
+#[test]
+fn referrers_of_an_instance() {
+    let content = format!("Host backup: Host(prod-01)\n{NESTED}");
+    let context = loads(&content).unwrap();
+
+    let referrers = context.referrers_of("Host", "prod-01");
+    let paths = referrers
+        .iter()
+        .map(|referrer| referrer.path.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        ["backup", "Application[web].host", "Application[db].host"]
+    );
+    assert_eq!(referrers[0].owner, ReferenceOwner::Global("backup".into()));
+    let app = referrers[2].instance(&context).unwrap();
+    assert_eq!(
+        app.get_attribute("service").unwrap().to_template_text(),
+        "db"
+    );
+
+    assert_eq!(
+        context.referrers_of("Rack", "r1")[0].path,
+        "Host[prod-01].rack"
+    );
+    assert!(context.referrers_of("Application", "web").is_empty());
+    assert!(context.referrers_of("Host", "missing").is_empty());
+}
+
+#[test]
+fn unused_instances_and_globals() {
+    let content = format!(
+        "str domain: example.com\nstr owner: ops\nstr unused: nobody\n{}",
+        NESTED
+            .replace(
+                "  Host host:\n",
+                "  Host host:\n  str url: {service}.{global.domain}\n"
+            )
+            .replace(
+                "  - prod-01, Rack(r1)\n",
+                "  - prod-01, Rack(r1)\n  - prod-02, Rack(r1)\n",
+            )
+            .replace(
+                "  str region: us-east\n",
+                "  str region: us-east\n  str contact: {owner}\n"
+            )
+            .replace("  - r1\n", "  - r1\n  - r2\n")
+    );
+    let context = loads(&content).unwrap();
+
+    let unused = context
+        .unused_instances()
+        .into_iter()
+        .map(|instance| instance.path)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        unused,
+        [
+            "Application[web]",
+            "Application[db]",
+            "Host[prod-02]",
+            "Rack[r2]"
+        ]
+    );
+    assert_eq!(context.unused_globals(), ["unused"]);
+}
@@ -0,0 +1,94 @@
+use serde_json::json;
+use tyco_rust::{loads, BytesEncoding, JsonOptions};
+
+const CONFIG: &str = r#"
+str timezone: UTC
+int[] ports: [80, 443]
+?str owner: ops
+
+Host:
+ *str hostname:
+  uint cores: 4
+  ?str notes:
+  date installed:
+  bytes key:
+  - web-01, installed: 2024-01-31, key: hex"abcd"
+
+Application:
+ *str name:
+  Host host:
+  Host[] replicas:
+  ?Application parent:
+  - api, Host(web-01), [], parent: null
+
+Job:
+  str command:
+  - "backup"
+"#;
+
+#[test]
+fn json_schema_describes_to_json_output() {
+    let context = loads(CONFIG).unwrap();
+    let schema = context.to_json_schema();
+
+    assert_eq!(
+        schema["$schema"],
+        "https://json-schema.org/draft/2020-12/schema"
+    );
+    assert_eq!(
+        schema["required"],
+        json!(["timezone", "ports", "owner", "Host", "Application"])
+    );
+    assert_eq!(schema["properties"]["timezone"], json!({"type": "string"}));
+    assert_eq!(
+        schema["properties"]["owner"],
+        json!({"anyOf": [{"type": "string"}, {"type": "null"}]})
+    );
+    assert_eq!(
+        schema["properties"]["ports"],
+        json!({"type": "array", "items": {"type": "integer"}})
+    );
+    assert_eq!(
+        schema["properties"]["Host"],
+        json!({"type": "array", "items": {"$ref": "#/$defs/Host"}})
+    );
+    assert!(schema["properties"].get("Job").is_none());
+
+    assert_eq!(
+        schema["$defs"]["Host"],
+        json!({
+            "type": "object",
+            "properties": {
+                "hostname": {"type": "string"},
+                "cores": {"type": "integer", "minimum": 0, "default": 4},
+                "notes": {"anyOf": [{"type": "string"}, {"type": "null"}]},
+                "installed": {"type": "string", "format": "date"},
+                "key": {"type": "string", "contentEncoding": "base64"},
+            },
+            "required": ["hostname", "cores", "installed", "key"],
+            "additionalProperties": false,
+        })
+    );
+    let application = &schema["$defs"]["Application"]["properties"];
+    assert_eq!(application["host"], json!({"$ref": "#/$defs/Host"}));
+    assert_eq!(
+        application["replicas"],
+        json!({"type": "array", "items": {"$ref": "#/$defs/Host"}})
+    );
+    assert_eq!(
+        application["parent"],
+        json!({"anyOf": [{"$ref": "#/$defs/Application"}, {"type": "null"}]})
+    );
+    assert_eq!(
+        schema["$defs"]["Job"]["properties"]["command"],
+        json!({"type": "string"})
+    );
+
+    let options = JsonOptions {
+        bytes: BytesEncoding::Hex,
+    };
+    assert_eq!(
+        context.to_json_schema_with(&options)["$defs"]["Host"]["properties"]["key"],
+        json!({"type": "string", "pattern": "^([0-9a-f]{2})*$"})
+    );
+}
@@ -0,0 +1,199 @@
+use serde_json::json;
+use tyco_rust::{loads, BytesEncoding, JsonOptions, TycoError, TycoValue};
+
+#[test]
+fn unsigned_and_decimal_values() {
+    let context = loads(
+        "uint id: 18446744073709551615\nu64 mask: 0xFFFFFFFFFFFFFFFF\ndecimal price: 19.990\n",
+    )
+    .unwrap();
+
+    assert!(matches!(context.globals()["id"], TycoValue::UInt(u64::MAX)));
+    assert!(matches!(context.globals()["mask"], TycoValue::UInt(u64::MAX)));
+    let json = context.to_json();
+    assert_eq!(json["id"], json!(u64::MAX));
+    if cfg!(feature = "arbitrary-precision") {
+        assert_eq!(json["price"].to_string(), "19.990");
+    } else {
+        assert_eq!(json["price"], json!("19.990"));
+    }
+}
+
+#[test]
+fn integer_overflow_is_typed() {
+    let err = loads("int id: 18446744073709551615\n").unwrap_err();
+    match err {
+        TycoError::NumericOverflow {
+            literal,
+            type_name,
+            span,
+        } => {
+            assert_eq!(literal, "18446744073709551615");
+            assert_eq!(type_name, "int");
+            assert_eq!(span.unwrap().column, 9);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+
+    assert!(matches!(
+        loads("uint id: 18446744073709551616\n").unwrap_err(),
+        TycoError::NumericOverflow { type_name, .. } if type_name == "uint"
+    ));
+    assert!(loads("uint id: -1\n").is_err());
+}
+
+#[cfg(feature = "bigint")]
+#[test]
+fn big_integers() {
+    let context = loads("bigint huge: -0x10000000000000000\n").unwrap();
+    assert_eq!(
+        context.globals()["huge"].to_template_text(),
+        "-18446744073709551616"
+    );
+}
+
+#[test]
+fn numeric_literal_forms() {
+    let context = loads(
+        "int big: 1_000_000\nint min: -0x8000000000000000\nint low: -9223372036854775808\n\
+         float sci: 6.022_140e23\nfloat pos: +inf\nfloat neg: -inf\nfloat missing: -nan\n",
+    )
+    .unwrap();
+
+    assert!(matches!(context.globals()["big"], TycoValue::Int(1_000_000)));
+    assert!(matches!(context.globals()["min"], TycoValue::Int(i64::MIN)));
+    assert!(matches!(context.globals()["low"], TycoValue::Int(i64::MIN)));
+    assert!(matches!(context.globals()["sci"], TycoValue::Float(v) if v == 6.02214e23));
+
+    let json = context.to_json();
+    assert_eq!(json["pos"], json!("inf"));
+    assert_eq!(json["neg"], json!("-inf"));
+    assert_eq!(json["missing"], json!("nan"));
+
+    for bad in ["int x: 1__0", "int x: _1", "int x: 1_", "float x: 1._5", "int x: 1.5"] {
+        assert!(loads(bad).is_err(), "{bad} should be rejected");
+    }
+    assert!(matches!(
+        loads("int x: 0x8000000000000000").unwrap_err(),
+        TycoError::NumericOverflow { .. }
+    ));
+}
+
+#[test]
+fn bytes_literals() {
+    let content = "bytes key: b64\"3q2+7w==\"\n\
+                   bytes[] fingerprints: [hex\"deadbeef\", hex\"00ff\"]\n";
+    let context = loads(content).unwrap();
+    assert!(
+        matches!(&context.globals()["key"], TycoValue::Bytes(v) if v == &[0xde, 0xad, 0xbe, 0xef])
+    );
+
+    let json = context.to_json();
+    assert_eq!(json["key"], json!("3q2+7w=="));
+    assert_eq!(json["fingerprints"], json!(["3q2+7w==", "AP8="]));
+
+    let options = JsonOptions {
+        bytes: BytesEncoding::Hex,
+    };
+    assert_eq!(context.to_json_with(&options)["key"], json!("deadbeef"));
+}
+
+#[test]
+fn bytes_length_errors_point_at_literal() {
+    let err = loads("bytes[] keys: [hex\"00\", hex\"abc\"]\n").unwrap_err();
+    match err {
+        TycoError::Parse { message, span } => {
+            assert!(message.contains("even number of digits"), "{message}");
+            assert_eq!(span.unwrap().column, 25);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+    assert!(loads("bytes key: b64\"abc\"\n").is_err());
+}
+
+#[test]
+fn instance_row_errors_report_their_line() {
+    let err = loads("Host:\n *str name:\n  bytes key:\n  - a, hex\"00\"\n  - b, hex\"abc\"\n").unwrap_err();
+    match err {
+        TycoError::Parse { span, .. } => {
+            let span = span.unwrap();
+            assert_eq!(span.line, 5);
+            assert_eq!(span.column, 8);
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn typed_accessors() {
+    let context = loads(
+        r#"
+str timezone: UTC
+int[] ports: [80, 443]
+?str alias: null
+
+Host:
+ *str hostname:
+  int cores:
+  uint memory:
+  float load:
+  bool active:
+  date since:
+  - prod-01, 16, 64, 0.5, true, 2024-01-31
+"#,
+    )
+    .unwrap();
+    let globals = context.globals();
+    assert_eq!(globals["timezone"].as_str(), Some("UTC"));
+    assert_eq!(globals["timezone"].as_i64(), None);
+    assert!(globals["alias"].is_null());
+    assert_eq!(Vec::<u16>::try_from(&globals["ports"]).unwrap(), [80, 443]);
+    assert_eq!(Option::<String>::try_from(&globals["alias"]).unwrap(), None);
+    assert_eq!(globals["ports"].as_array().map(<[_]>::len), Some(2));
+
+    let host = context
+        .get_struct("Host")
+        .unwrap()
+        .find_by_primary_key("prod-01")
+        .unwrap();
+    assert_eq!(host["cores"].as_i64(), Some(16));
+    assert_eq!(host["memory"].as_u64(), Some(64));
+    assert_eq!(host["load"].as_f64(), Some(0.5));
+    assert_eq!(host["active"].as_bool(), Some(true));
+    assert_eq!(host.get_as::<&str>("hostname").unwrap(), "prod-01");
+    assert_eq!(host.get_as::<u8>("cores").unwrap(), 16);
+    assert_eq!(host.get_as::<f64>("cores").unwrap(), 16.0);
+    assert_eq!(host.get_as::<String>("since").unwrap(), "2024-01-31");
+    assert_eq!(host.get_as::<Option<i64>>("missing").unwrap(), None);
+
+    let err = host.get_as::<bool>("cores").unwrap_err();
+    assert!(matches!(
+        &err,
+        TycoError::TypeMismatch { field: Some(field), expected, found }
+            if field == "Host.cores" && expected == "bool" && found == "int"
+    ));
+    assert_eq!(
+        err.to_string(),
+        "Type mismatch for field 'Host.cores': expected bool, found int"
+    );
+    assert_eq!(
+        host.get_as::<i64>("missing").unwrap_err().to_string(),
+        "Type mismatch for field 'Host.missing': expected int, found null"
+    );
+    assert_eq!(
+        i64::try_from(&globals["timezone"]).unwrap_err().to_string(),
+        "Type mismatch: expected int, found str"
+    );
+    assert!(u8::try_from(&TycoValue::Int(300)).is_err());
+}
+
+#[test]
+fn inline_instance_arguments_keep_lenient_bools() {
+    let context = loads(
+        "Flag:\n  str name:\n  bool on:\n\nUse:\n *str id:\n  Flag flag:\n  - x, Flag(a, True)\n  - y, Flag(b, yes)\n",
+    )
+    .unwrap();
+    let json = context.to_json();
+    assert_eq!(json["Use"][0]["flag"]["on"], json!(true));
+    assert_eq!(json["Use"][1]["flag"]["on"], json!(false));
+}